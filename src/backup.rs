@@ -0,0 +1,150 @@
+//! Copies of a counter file kept next to it as `<path>.<time>.bak`, where the time is
+//! in UTC, e.g. `counts.txt.20240506T070809Z.bak`
+//!
+//! Backups made by the rotation (see [`Options::backup_every`](crate::Options)) are
+//! named `<path>.<time>.auto.bak`, and only those are removed by it.
+
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::journal;
+use crate::stats;
+use crate::{Counter, parse_counters};
+
+/// A backup of a counter file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Backup {
+    pub path: PathBuf,
+    /// When it was made, as a Unix timestamp
+    pub time: u64,
+    /// Whether it was made by the rotation, which removes it once it is old enough
+    pub automatic: bool,
+    // Number telling apart backups made within the same second
+    sequence: u32,
+}
+
+impl Backup {
+    /// The counters in the backup, or None if it holds something else
+    pub fn counters(&self) -> io::Result<Option<Vec<Counter>>> {
+        let contents = fs::read(&self.path)?;
+        let counters = String::from_utf8(contents)
+            .ok()
+            .and_then(|contents| parse_counters(&contents));
+        Ok(counters.filter(|counters| !counters.is_empty()))
+    }
+}
+
+/// Copy the file at `path` to a new backup named after the current time
+///
+/// Returns the backup's path. An existing backup is never replaced: a number is added
+/// to the names of further backups made within the same second.
+pub fn create(path: &Path) -> io::Result<PathBuf> {
+    make(path, false)
+}
+
+/// Copy the file at `path` to a new automatic backup, then remove all but the `keep`
+/// most recent automatic backups
+pub fn rotate(path: &Path, keep: usize) -> io::Result<PathBuf> {
+    let backup = make(path, true)?;
+    for old in list(path)?.iter().filter(|b| b.automatic).skip(keep) {
+        fs::remove_file(&old.path)?;
+    }
+    Ok(backup)
+}
+
+/// The backups of the file at `path`, most recent first
+pub fn list(path: &Path) -> io::Result<Vec<Backup>> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut backups = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let parsed = name
+            .to_str()
+            .and_then(|name| name.strip_prefix(&*file_name)?.strip_prefix('.'))
+            .and_then(parse_name);
+        if let Some((time, sequence, automatic)) = parsed {
+            backups.push(Backup {
+                path: path.with_file_name(name),
+                time,
+                automatic,
+                sequence,
+            });
+        }
+    }
+    backups.sort_by_key(|b| (b.time, b.sequence));
+    backups.reverse();
+    Ok(backups)
+}
+
+fn make(path: &Path, automatic: bool) -> io::Result<PathBuf> {
+    let now = journal::now();
+    let stamp: String = stats::format_time(now)
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .collect();
+    // Numbered after any made within the same second, so that they sort in order
+    let made = list(path)?;
+    let first = made
+        .iter()
+        .filter(|b| b.time == now)
+        .map(|b| b.sequence + 1)
+        .max();
+    for n in first.unwrap_or(1).. {
+        let mut name = OsString::from(path.file_name().unwrap_or_default());
+        name.push(format!(".{stamp}"));
+        if n > 1 {
+            name.push(format!("-{n}"));
+        }
+        name.push(if automatic { ".auto.bak" } else { ".bak" });
+        let backup = path.with_file_name(name);
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&backup)
+        {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+        if let Err(e) = fs::copy(path, &backup) {
+            _ = fs::remove_file(&backup);
+            return Err(e);
+        }
+        return Ok(backup);
+    }
+    unreachable!("ran out of backup names")
+}
+
+// The time, sequence number and whether it is automatic from the end of a backup's file
+// name, e.g. `20240506T070809Z-2.auto.bak`
+fn parse_name(name: &str) -> Option<(u64, u32, bool)> {
+    let name = name.strip_suffix(".bak")?;
+    let (name, automatic) = name
+        .strip_suffix(".auto")
+        .map_or((name, false), |name| (name, true));
+    let (stamp, sequence) = match name.split_once('-') {
+        Some((stamp, sequence)) => (stamp, sequence.parse().ok()?),
+        None => (name, 1),
+    };
+    let digits = |range: std::ops::Range<usize>| stamp.get(range);
+    if stamp.len() != 16 || stamp.get(8..9)? != "T" || !stamp.ends_with('Z') {
+        return None;
+    }
+    let time = format!(
+        "{}-{}-{}T{}:{}:{}Z",
+        digits(0..4)?,
+        digits(4..6)?,
+        digits(6..8)?,
+        digits(9..11)?,
+        digits(11..13)?,
+        digits(13..15)?
+    );
+    let time = stats::parse_time(&time)?;
+    Some((time, sequence, automatic))
+}
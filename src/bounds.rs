@@ -0,0 +1,154 @@
+/// What happens when a change would take a counter outside its bounds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Stop at the bound
+    Saturate,
+    /// Continue from the opposite bound, like a mechanical counter
+    Wrap,
+    /// Leave the count unchanged
+    #[default]
+    Reject,
+}
+
+/// Inclusive range that counter values are kept within
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Bounds {
+    pub min: i64,
+    pub max: i64,
+    pub policy: Policy,
+}
+
+impl Default for Bounds {
+    fn default() -> Self {
+        Self {
+            min: i64::MIN,
+            max: i64::MAX,
+            policy: Policy::default(),
+        }
+    }
+}
+
+/// Result of applying a change to a counter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The change was applied as requested
+    Applied(i64),
+    /// The count was stopped at a bound
+    Saturated(i64),
+    /// The count went past a bound and continued from the other one
+    Wrapped(i64),
+}
+
+impl Outcome {
+    /// The new count
+    #[must_use]
+    pub const fn value(self) -> i64 {
+        match self {
+            Self::Applied(v) | Self::Saturated(v) | Self::Wrapped(v) => v,
+        }
+    }
+}
+
+impl Bounds {
+    /// Decide what a change that would produce `target` actually does, or None if the
+    /// change is rejected (always, if `min` is above `max`). The target is wider than
+    /// `i64` so that overflowing the type itself is handled like any bound.
+    #[must_use]
+    pub fn apply(&self, target: i128) -> Option<Outcome> {
+        let (min, max) = (i128::from(self.min), i128::from(self.max));
+        if min > max {
+            return None;
+        }
+        if (min..=max).contains(&target) {
+            return Some(Outcome::Applied(narrow(target)));
+        }
+
+        match self.policy {
+            Policy::Saturate => Some(Outcome::Saturated(narrow(target.clamp(min, max)))),
+            Policy::Wrap => {
+                let span = max - min + 1;
+                Some(Outcome::Wrapped(narrow(
+                    min + (target - min).rem_euclid(span),
+                )))
+            }
+            Policy::Reject => None,
+        }
+    }
+}
+
+// Only called with values already known to lie within `i64` bounds
+fn narrow(n: i128) -> i64 {
+    i64::try_from(n).unwrap_or_else(|_| unreachable!("value {n} out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn bounds(min: i64, max: i64, policy: Policy) -> Bounds {
+        Bounds { min, max, policy }
+    }
+
+    #[test]
+    fn within_bounds() {
+        for policy in [Policy::Saturate, Policy::Wrap, Policy::Reject] {
+            let bounds = bounds(0, 10, policy);
+            assert_eq!(bounds.apply(0), Some(Outcome::Applied(0)));
+            assert_eq!(bounds.apply(10), Some(Outcome::Applied(10)));
+        }
+    }
+
+    #[test]
+    fn saturate() {
+        let bounds = bounds(0, 10, Policy::Saturate);
+        assert_eq!(bounds.apply(11), Some(Outcome::Saturated(10)));
+        assert_eq!(bounds.apply(-1), Some(Outcome::Saturated(0)));
+        let full = Bounds {
+            policy: Policy::Saturate,
+            ..Bounds::default()
+        };
+        let past = i128::from(i64::MAX) + 1;
+        assert_eq!(full.apply(past), Some(Outcome::Saturated(i64::MAX)));
+    }
+
+    #[test]
+    fn reject() {
+        let bounds = bounds(0, 10, Policy::Reject);
+        assert_eq!(bounds.apply(11), None);
+        assert_eq!(bounds.apply(-1), None);
+    }
+
+    #[test]
+    fn wrap() {
+        let bounds = bounds(0, 9, Policy::Wrap);
+        assert_eq!(bounds.apply(10), Some(Outcome::Wrapped(0)));
+        assert_eq!(bounds.apply(-1), Some(Outcome::Wrapped(9)));
+        assert_eq!(bounds.apply(25), Some(Outcome::Wrapped(5)));
+        assert_eq!(bounds.apply(-25), Some(Outcome::Wrapped(5)));
+    }
+
+    #[test]
+    fn wrap_single_value() {
+        let bounds = bounds(3, 3, Policy::Wrap);
+        assert_eq!(bounds.apply(4), Some(Outcome::Wrapped(3)));
+        assert_eq!(bounds.apply(-100), Some(Outcome::Wrapped(3)));
+    }
+
+    #[test]
+    fn wrap_full_range() {
+        let bounds = bounds(i64::MIN, i64::MAX, Policy::Wrap);
+        let (min, max) = (i128::from(i64::MIN), i128::from(i64::MAX));
+        assert_eq!(bounds.apply(max + 1), Some(Outcome::Wrapped(i64::MIN)));
+        assert_eq!(bounds.apply(min - 1), Some(Outcome::Wrapped(i64::MAX)));
+        assert_eq!(bounds.apply(max * 2), Some(Outcome::Wrapped(-2)));
+    }
+
+    #[test]
+    fn empty_bounds() {
+        for policy in [Policy::Saturate, Policy::Wrap, Policy::Reject] {
+            let bounds = bounds(10, 5, policy);
+            assert_eq!(bounds.apply(7), None);
+            assert_eq!(bounds.apply(20), None);
+        }
+    }
+}
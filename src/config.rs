@@ -0,0 +1,394 @@
+//! Optional config file, by default `$XDG_CONFIG_HOME/counter/config.toml` (or
+//! `~/.config/counter/config.toml`), holding key bindings and hook commands:
+//!
+//! ```toml
+//! [keys]
+//! increment = ["+", "space", "kp-+"]
+//! quit = "ctrl-q"
+//!
+//! [hooks]
+//! on-change = "notify-send \"$COUNTER_NAME: $COUNTER_NEW\""
+//! timeout = 5
+//! ```
+//!
+//! Listing an action replaces its default keys. A key given explicitly (in the file or
+//! with `--bind`) takes precedence over another action's default binding for it, but the
+//! same key may not be given explicitly for two different actions.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventState, KeyModifiers};
+
+use crate::Action;
+use counter::Hooks;
+use counter::toml_lite::{self, Table, Value};
+
+const DEFAULT_KEYS: &[(&str, &[&str])] = &[
+    ("increment", &["+", "=", "space"]),
+    ("decrement", &["-", "_", "backspace"]),
+    ("big-increment", &["]", "pageup"]),
+    ("big-decrement", &["[", "pagedown"]),
+    ("cancel", &["esc"]),
+    ("next", &["tab", "right"]),
+    ("previous", &["shift-tab", "left"]),
+    ("undo", &["u", "ctrl-z"]),
+    ("redo", &["ctrl-r", "ctrl-y"]),
+    ("quit", &["q", "Q", "ctrl-c"]),
+];
+
+pub struct Config {
+    /// Key presses and the action each one triggers
+    pub keys: HashMap<KeyEvent, Action>,
+    /// Commands from the `[hooks]` table
+    pub hooks: Hooks,
+}
+
+// What the config file holds: every binding given explicitly (action, key, where it
+// came from), the actions they cover (which may have been given an empty list of keys),
+// and the hooks
+#[derive(Default)]
+struct FileConfig {
+    bindings: Vec<(String, String, String)>,
+    actions: Vec<String>,
+    hooks: Hooks,
+}
+
+impl Config {
+    /// Whether any binding is for a keypad key, which terminals only report as such
+    /// when the keyboard enhancement protocol is enabled
+    pub fn uses_keypad(&self) -> bool {
+        self.keys
+            .keys()
+            .any(|k| k.state.contains(KeyEventState::KEYPAD))
+    }
+
+    /// Short descriptions of the main bindings, e.g. "+ increment", leaving out those
+    /// that change a count if `read_only`
+    pub fn help(&self, read_only: bool) -> Vec<String> {
+        const SHOWN: &[(Action, &str)] = &[
+            (Action::Increment, "increment"),
+            (Action::Decrement, "decrement"),
+            (Action::BigIncrement, "big increment"),
+            (Action::BigDecrement, "big decrement"),
+            (Action::Next, "next counter"),
+            (Action::Undo, "undo"),
+            (Action::Redo, "redo"),
+            (Action::Quit, "quit"),
+        ];
+        SHOWN
+            .iter()
+            .filter(|(action, _)| !read_only || matches!(action, Action::Next | Action::Quit))
+            .filter_map(|(action, label)| {
+                // Show the shortest key bound to each action, preferring lower case
+                let name = (self.keys.iter())
+                    .filter(|(_, a)| *a == action)
+                    .map(|(key, _)| key_name(key))
+                    .min_by_key(|name| (name.len(), name.to_lowercase() != *name, name.clone()))?;
+                Some(format!("{name} {label}"))
+            })
+            .collect()
+    }
+}
+
+pub fn default_path() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config_dir.join("counter").join("config.toml"))
+}
+
+/// Load the config file at `path`, or at the default path if there is one there, and
+/// apply `binds` (`ACTION=KEY` strings from the command line) on top of it
+pub fn load(path: Option<&Path>, binds: &[String]) -> Result<Config, String> {
+    let mut from_file = FileConfig::default();
+    let file = path.map_or_else(
+        || default_path().filter(|path| path.exists()),
+        |path| Some(path.to_path_buf()),
+    );
+    if let Some(file) = file {
+        let contents = match fs::read_to_string(&file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound && path.is_none() => String::new(),
+            Err(e) => return Err(format!("{}: {e}", file.display())),
+        };
+        let source = file.display().to_string();
+        from_file = parse_file(&contents).map_err(|e| format!("{source}: {e}"))?;
+    }
+    let FileConfig {
+        bindings: mut explicit,
+        actions: mut configured,
+        hooks,
+    } = from_file;
+
+    let mut from_cli: Vec<(String, String, String)> = Vec::new();
+    for bind in binds {
+        let Some((action, key)) = bind.split_once('=') else {
+            return Err(format!("--bind '{bind}': expected ACTION=KEY"));
+        };
+        from_cli.push((action.to_string(), key.to_string(), "--bind".to_string()));
+    }
+    // Actions bound on the command line replace the keys from the file
+    explicit.retain(|(action, _, _)| !from_cli.iter().any(|(a, _, _)| a == action));
+    configured.extend(from_cli.iter().map(|(action, _, _)| action.clone()));
+    explicit.extend(from_cli);
+
+    let mut keys: HashMap<KeyEvent, Action> = HashMap::new();
+    let mut origins: HashMap<KeyEvent, (&str, &str)> = HashMap::new();
+    if let Some(action) = configured.iter().find(|a| parse_action(a).is_none()) {
+        return Err(format!("unknown action '{action}'"));
+    }
+    for (action_name, key_name, source) in &explicit {
+        let action = parse_action(action_name).unwrap_or_else(|| unreachable!());
+        let key = parse_key(key_name)
+            .map_err(|e| format!("{source}: invalid key '{key_name}' for '{action_name}': {e}"))?;
+        if let Some(&other) = keys.get(&key)
+            && other != action
+        {
+            let (other_name, other_source) = origins[&key];
+            return Err(format!(
+                "key '{key_name}' is bound to both '{other_name}' ({other_source}) and \
+                 '{action_name}' ({source})"
+            ));
+        }
+        keys.insert(key, action);
+        origins.insert(key, (action_name, source));
+    }
+
+    // Defaults fill in for actions that weren't configured, as long as their keys are
+    // still free
+    let defaults = default_bindings();
+    for (action_name, key_name) in &defaults {
+        if configured.contains(action_name) {
+            continue;
+        }
+        let action = parse_action(action_name).unwrap_or_else(|| unreachable!());
+        let key = parse_key(key_name).unwrap_or_else(|e| unreachable!("{key_name}: {e}"));
+        keys.entry(key).or_insert(action);
+    }
+    // Digits build a repeat count for the next increment or decrement, e.g. "25+"
+    for d in 0..=9 {
+        let key = KeyEvent::new(KeyCode::Char(char::from(b'0' + d)), KeyModifiers::empty());
+        keys.entry(key).or_insert(Action::Digit(d));
+    }
+
+    if !keys.values().any(|&a| a == Action::Quit) {
+        return Err("no key is bound to 'quit'".to_string());
+    }
+    Ok(Config { keys, hooks })
+}
+
+fn default_bindings() -> Vec<(String, String)> {
+    let mut bindings: Vec<(String, String)> = DEFAULT_KEYS
+        .iter()
+        .flat_map(|(action, keys)| keys.iter().map(|key| (action.to_string(), key.to_string())))
+        .collect();
+    // F1..F12 select a counter directly
+    for i in 1..=12 {
+        bindings.push((format!("select-{i}"), format!("f{i}")));
+    }
+    bindings
+}
+
+// Read the `[keys]` table, where each action maps to a key or a list of keys, and the
+// `[hooks]` table
+fn parse_file(contents: &str) -> Result<FileConfig, String> {
+    let document = toml_lite::parse(contents).map_err(|e| e.to_string())?;
+    if let Some((key, _)) = document.root().entries.first() {
+        return Err(format!("unexpected key '{key}' outside of a table"));
+    }
+    if let Some(table) = document
+        .named_tables()
+        .find(|t| t.name != "keys" && t.name != "hooks")
+    {
+        return Err(format!("unknown table [{}]", table.name));
+    }
+
+    let mut config = FileConfig::default();
+    if let Some(table) = document.table("hooks") {
+        config.hooks = parse_hooks(table)?;
+    }
+    let Some(table) = document.table("keys") else {
+        return Ok(config);
+    };
+    let FileConfig {
+        bindings, actions, ..
+    } = &mut config;
+    for (action, value) in &table.entries {
+        actions.push(action.clone());
+        let keys = match value {
+            Value::String(key) => vec![key.as_str()],
+            Value::Array(keys) => keys
+                .iter()
+                .map(|key| {
+                    key.as_str()
+                        .ok_or_else(|| format!("[keys] {action}: keys must be strings"))
+                })
+                .collect::<Result<_, _>>()?,
+            _ => return Err(format!("[keys] {action}: expected a key or a list of keys")),
+        };
+        for key in keys {
+            bindings.push((action.clone(), key.to_string(), "config file".to_string()));
+        }
+    }
+    Ok(config)
+}
+
+// Read the `[hooks]` table: a command for each event, and the timeout in seconds
+fn parse_hooks(table: &Table) -> Result<Hooks, String> {
+    let mut hooks = Hooks::default();
+    for (key, value) in &table.entries {
+        if key == "timeout" {
+            let seconds = value
+                .as_integer()
+                .and_then(|seconds| u64::try_from(seconds).ok())
+                .ok_or_else(|| "[hooks] timeout: expected a number of seconds".to_string())?;
+            hooks.timeout = Some(Duration::from_secs(seconds));
+            continue;
+        }
+        let hook = match key.as_str() {
+            "on-change" => &mut hooks.on_change,
+            "on-increment" => &mut hooks.on_increment,
+            "on-decrement" => &mut hooks.on_decrement,
+            "on-target" => &mut hooks.on_target,
+            "on-exit" => &mut hooks.on_exit,
+            _ => return Err(format!("[hooks] unknown hook '{key}'")),
+        };
+        let command = value
+            .as_str()
+            .ok_or_else(|| format!("[hooks] {key}: expected a command"))?;
+        *hook = Some(command.to_string());
+    }
+    Ok(hooks)
+}
+
+fn parse_action(name: &str) -> Option<Action> {
+    let action = match name {
+        "increment" => Action::Increment,
+        "decrement" => Action::Decrement,
+        "big-increment" => Action::BigIncrement,
+        "big-decrement" => Action::BigDecrement,
+        "cancel" => Action::Cancel,
+        "next" => Action::Next,
+        "previous" => Action::Previous,
+        "undo" => Action::Undo,
+        "redo" => Action::Redo,
+        "quit" => Action::Quit,
+        _ => {
+            let n: usize = name.strip_prefix("select-")?.parse().ok()?;
+            return (1..=12).contains(&n).then(|| Action::Select(n - 1));
+        }
+    };
+    Some(action)
+}
+
+/// Parse a key description such as `q`, `space`, `ctrl-z`, `shift-f5` or `kp-+`
+pub fn parse_key(name: &str) -> Result<KeyEvent, String> {
+    let mut modifiers = KeyModifiers::empty();
+    let mut state = KeyEventState::empty();
+    let mut rest = name;
+    // Peel off prefixes, taking care that "-" and "ctrl--" name the minus key
+    while let Some((prefix, tail)) = rest.split_once('-')
+        && !tail.is_empty()
+    {
+        match prefix.to_ascii_lowercase().as_str() {
+            "ctrl" | "control" => modifiers |= KeyModifiers::CONTROL,
+            "alt" | "meta" => modifiers |= KeyModifiers::ALT,
+            "shift" => modifiers |= KeyModifiers::SHIFT,
+            "super" => modifiers |= KeyModifiers::SUPER,
+            "kp" | "keypad" => state |= KeyEventState::KEYPAD,
+            _ => break,
+        }
+        rest = tail;
+    }
+
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (None, _) => return Err("missing key name".to_string()),
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match rest.to_ascii_lowercase().as_str() {
+            "space" => KeyCode::Char(' '),
+            "enter" | "return" => KeyCode::Enter,
+            "tab" if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+            "tab" => KeyCode::Tab,
+            "backtab" => {
+                // Terminals report shift-tab as BackTab with the shift modifier
+                modifiers |= KeyModifiers::SHIFT;
+                KeyCode::BackTab
+            }
+            "backspace" => KeyCode::Backspace,
+            "esc" | "escape" => KeyCode::Esc,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "insert" => KeyCode::Insert,
+            "delete" | "del" => KeyCode::Delete,
+            lower => match lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                Some(n) if (1..=24).contains(&n) => KeyCode::F(n),
+                _ => return Err(format!("unknown key name '{rest}'")),
+            },
+        },
+    };
+
+    let mut key = KeyEvent::new(code, modifiers);
+    key.state = state;
+    Ok(key)
+}
+
+/// Describe a key in the syntax accepted by `parse_key`
+pub fn key_name(key: &KeyEvent) -> String {
+    let mut name = String::new();
+    if key.state.contains(KeyEventState::KEYPAD) {
+        name.push_str("kp-");
+    }
+    for (modifier, prefix) in [
+        (KeyModifiers::CONTROL, "ctrl-"),
+        (KeyModifiers::ALT, "alt-"),
+        (KeyModifiers::SUPER, "super-"),
+    ] {
+        if key.modifiers.contains(modifier) {
+            name.push_str(prefix);
+        }
+    }
+    // Shift is implied by upper case characters and BackTab
+    let shifted = matches!(key.code, KeyCode::Char(_) | KeyCode::BackTab);
+    if key.modifiers.contains(KeyModifiers::SHIFT) && !shifted {
+        name.push_str("shift-");
+    }
+
+    match key.code {
+        KeyCode::Char(' ') => name.push_str("space"),
+        KeyCode::Char(c) => name.push(c),
+        KeyCode::Enter => name.push_str("enter"),
+        KeyCode::Tab => name.push_str("tab"),
+        KeyCode::BackTab => name.push_str("shift-tab"),
+        KeyCode::Backspace => name.push_str("backspace"),
+        KeyCode::Esc => name.push_str("esc"),
+        KeyCode::Up => name.push_str("up"),
+        KeyCode::Down => name.push_str("down"),
+        KeyCode::Left => name.push_str("left"),
+        KeyCode::Right => name.push_str("right"),
+        KeyCode::Home => name.push_str("home"),
+        KeyCode::End => name.push_str("end"),
+        KeyCode::PageUp => name.push_str("pageup"),
+        KeyCode::PageDown => name.push_str("pagedown"),
+        KeyCode::Insert => name.push_str("insert"),
+        KeyCode::Delete => name.push_str("delete"),
+        KeyCode::F(n) => {
+            name.push('f');
+            name.push_str(&n.to_string());
+        }
+        code => name.push_str(&format!("{code:?}").to_ascii_lowercase()),
+    }
+    name
+}
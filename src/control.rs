@@ -0,0 +1,190 @@
+//! Control socket letting other local processes change the counter of a running session
+//!
+//! Clients connect to a Unix socket and send one command per line, each answered with
+//! one line: the counter's value, or `error <code>: <message>`.
+//!
+//! - `get`: the selected counter's value
+//! - `inc [N]`, `dec [N]`: add or subtract N (default: the step of a key press)
+//! - `set N`: set the value
+//! - `subscribe`: the value now and after every change, until the client disconnects
+//!
+//! Commands are handed to the interactive loop, which applies them like key presses.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+/// A command from a client
+pub enum Command {
+    Get,
+    Inc(Option<i64>),
+    Dec(Option<i64>),
+    Set(i64),
+    Subscribe,
+}
+
+/// A command with the channel for answering it. Subscribers keep the channel and are
+/// sent every new value.
+pub struct Request {
+    pub command: Command,
+    pub reply: Sender<String>,
+}
+
+/// Listening socket, removed when dropped
+pub struct Server {
+    path: PathBuf,
+    // Device and inode of the socket this instance created, so that only it is removed
+    socket: Option<(u64, u64)>,
+    requests: Receiver<Request>,
+}
+
+impl Server {
+    /// Listen on `path`, replacing a stale socket left by an instance that didn't exit
+    /// cleanly
+    pub fn start(path: &Path) -> io::Result<Self> {
+        let (sender, requests) = mpsc::channel();
+        imp::listen(path, sender)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            socket: imp::socket_id(path),
+            requests,
+        })
+    }
+
+    /// Commands received since the last call
+    pub fn requests(&self) -> impl Iterator<Item = Request> {
+        self.requests.try_iter()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if self.socket.is_some() && imp::socket_id(&self.path) == self.socket {
+            _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+// Parse a command line, e.g. "inc 5"
+fn parse(line: &str) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let command = words.next().unwrap_or_default();
+    let amount = words.next().map(str::parse::<i64>).transpose();
+    if words.next().is_some() {
+        return Err(format!("too many arguments to '{command}'"));
+    }
+    let Ok(amount) = amount else {
+        return Err(format!("invalid number for '{command}'"));
+    };
+    match (command, amount) {
+        ("get", None) => Ok(Command::Get),
+        ("inc", amount) => Ok(Command::Inc(amount)),
+        ("dec", amount) => Ok(Command::Dec(amount)),
+        ("set", Some(value)) => Ok(Command::Set(value)),
+        ("set", None) => Err("'set' needs a value".to_string()),
+        ("subscribe", None) => Ok(Command::Subscribe),
+        ("get" | "subscribe", Some(_)) => Err(format!("'{command}' takes no arguments")),
+        _ => Err(format!("unknown command '{command}'")),
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::fs;
+    use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+    use std::os::unix::fs::{FileTypeExt, MetadataExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+    use std::sync::mpsc::{self, Sender};
+    use std::thread;
+
+    use super::{Command, Request, parse};
+
+    pub fn listen(path: &Path, requests: Sender<Request>) -> io::Result<()> {
+        let listener = match UnixListener::bind(path) {
+            Err(e) if e.kind() == ErrorKind::AddrInUse => {
+                if UnixStream::connect(path).is_ok() {
+                    return Err(io::Error::new(
+                        ErrorKind::AddrInUse,
+                        format!("{} is in use by another instance", path.display()),
+                    ));
+                }
+                // Bind also fails this way for other files, which are not ours to remove
+                if !fs::symlink_metadata(path)?.file_type().is_socket() {
+                    return Err(io::Error::new(
+                        ErrorKind::AlreadyExists,
+                        format!("{}: path exists and is not a socket", path.display()),
+                    ));
+                }
+                fs::remove_file(path)?;
+                UnixListener::bind(path)?
+            }
+            result => result?,
+        };
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let requests = requests.clone();
+                thread::spawn(move || serve(stream, &requests));
+            }
+        });
+        Ok(())
+    }
+
+    // Device and inode of the socket at `path`, or None if there is no socket there
+    pub fn socket_id(path: &Path) -> Option<(u64, u64)> {
+        let metadata = fs::symlink_metadata(path).ok()?;
+        metadata
+            .file_type()
+            .is_socket()
+            .then(|| (metadata.dev(), metadata.ino()))
+    }
+
+    // Answer the commands sent over one connection, until it is closed or the session
+    // ends
+    fn serve(stream: UnixStream, requests: &Sender<Request>) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let command = match parse(&line) {
+                Ok(command) => command,
+                Err(e) => {
+                    writeln!(writer, "error usage: {e}")?;
+                    continue;
+                }
+            };
+            let subscribe = matches!(command, Command::Subscribe);
+            let (reply, answers) = mpsc::channel();
+            if requests.send(Request { command, reply }).is_err() {
+                return Ok(());
+            }
+            // A subscriber gets every answer; anyone else just the one
+            for answer in answers.iter().take(if subscribe { usize::MAX } else { 1 }) {
+                writeln!(writer, "{answer}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::io::{self, ErrorKind};
+    use std::path::Path;
+    use std::sync::mpsc::Sender;
+
+    use super::Request;
+
+    pub fn listen(_path: &Path, _requests: Sender<Request>) -> io::Result<()> {
+        Err(io::Error::new(
+            ErrorKind::Unsupported,
+            "control sockets are only supported on Unix",
+        ))
+    }
+
+    pub const fn socket_id(_path: &Path) -> Option<(u64, u64)> {
+        None
+    }
+}
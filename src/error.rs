@@ -0,0 +1,127 @@
+//! Errors reported by counter operations
+
+use std::fmt::{self, Display};
+use std::io;
+use std::path::PathBuf;
+
+use crate::bounds::Bounds;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing storage failed
+    Io(io::Error),
+    /// The counter file holds something other than counters
+    Corrupt(PathBuf),
+    /// Another instance holds the exclusive lock on the counter file
+    Locked(PathBuf),
+    /// A change was rejected because the result would be outside the bounds
+    OutOfRange { value: i128, bounds: Bounds },
+    /// A change was rejected because the result doesn't fit in a counter
+    Overflow { value: i128 },
+    /// The counter's minimum, from the options or saved with it, is above its maximum
+    InvalidBounds { name: String, min: i64, max: i64 },
+    /// The user chose not to continue
+    Aborted,
+    /// The program was asked to stop by a signal (the signal number)
+    Interrupted(i32),
+    /// A countdown was left before reaching zero (the count remaining)
+    Incomplete(i64),
+    /// A change can't be undone or redone because something else has changed the
+    /// counter since (its count now)
+    Changed { name: String, count: i64 },
+}
+
+impl Error {
+    /// Process exit status for the error, so that scripts can tell failures apart
+    #[must_use]
+    pub const fn exit_code(&self) -> i32 {
+        match self {
+            Self::Io(_) => 1,
+            Self::InvalidBounds { .. } => 2,
+            Self::Corrupt(_) => 3,
+            Self::Locked(_) => 4,
+            Self::OutOfRange { .. } => 5,
+            Self::Overflow { .. } => 6,
+            Self::Aborted => 7,
+            Self::Incomplete(_) => 8,
+            Self::Changed { .. } => 9,
+            Self::Interrupted(signal) => 128 + *signal,
+        }
+    }
+
+    /// Short stable identifier for machine-readable output
+    #[must_use]
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::Io(_) => "io",
+            Self::Corrupt(_) => "corrupt",
+            Self::Locked(_) => "locked",
+            Self::OutOfRange { .. } => "out-of-range",
+            Self::Overflow { .. } => "overflow",
+            Self::InvalidBounds { .. } => "invalid-bounds",
+            Self::Aborted => "aborted",
+            Self::Interrupted(_) => "interrupted",
+            Self::Incomplete(_) => "incomplete",
+            Self::Changed { .. } => "changed",
+        }
+    }
+
+    /// The error for a change to `value` that `bounds` rejected
+    #[must_use]
+    pub fn rejected(value: i128, bounds: Bounds) -> Self {
+        if i64::try_from(value).is_ok() {
+            Self::OutOfRange { value, bounds }
+        } else {
+            Self::Overflow { value }
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Corrupt(path) => write!(f, "{} contains non-counter data", path.display()),
+            Self::Locked(path) => write!(
+                f,
+                "{} is in use by another process (see --lock)",
+                path.display()
+            ),
+            Self::OutOfRange { value, bounds } => write!(
+                f,
+                "Value {value} is out of range ({}..{})",
+                bounds.min, bounds.max
+            ),
+            Self::Overflow { value } => write!(f, "Value {value} is too large for a counter"),
+            Self::InvalidBounds { name, min, max } => write!(
+                f,
+                "Counter {name} would have a minimum ({min}) above its maximum ({max})"
+            ),
+            Self::Aborted => write!(f, "Aborted"),
+            Self::Interrupted(signal) => write!(f, "Terminated by signal {signal}"),
+            Self::Incomplete(remaining) => {
+                write!(f, "Countdown not complete ({remaining} remaining)")
+            }
+            Self::Changed { name, count } => {
+                write!(f, "Counter {name} has been changed to {count} since")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
@@ -0,0 +1,1063 @@
+//! Counters stored in a text file
+
+use std::cell::Cell;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::backup;
+use crate::bounds::{Bounds, Outcome};
+use crate::error::{Error, Result};
+use crate::history::{Change, History};
+use crate::hooks::Hooks;
+use crate::journal::{self, Entry, Journal, Op};
+use crate::lock::{FileLock, LockMode};
+use crate::metrics;
+use crate::stats;
+use crate::toml_lite::{self, Document, Table, Value};
+use crate::{Counter, CounterStore, DEFAULT_NAME, is_valid_name};
+
+/// How a [`FileCounter`] stores and checks its values
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Flush every change to disk before returning
+    pub data_sync: bool,
+    /// Record every change in a timestamped journal (`<path>.log`) and restore the
+    /// values by replaying it
+    pub journal: bool,
+    pub lock_mode: LockMode,
+    /// Bounds for every counter. Limits other than `i64::MIN` and `i64::MAX` are also
+    /// saved with the active counter, and apply to it from then on.
+    pub bounds: Bounds,
+    /// Number of changes that can be undone (0 disables the history in `<path>.history`)
+    pub history_depth: usize,
+    /// Never write to the file (and don't take the exclusive lock)
+    pub read_only: bool,
+    /// Replace a file that doesn't hold counters instead of failing
+    pub overwrite_invalid: bool,
+    /// Target to give the active counter (saved in the file)
+    pub target: Option<i64>,
+    /// Refuse (or, with [`Policy::Saturate`](crate::Policy::Saturate), stop at the
+    /// target) changes that would take a counter past its target
+    pub stop_at_target: bool,
+    /// Start a countdown of the active counter from this total (saved in the file). The
+    /// `value` given to [`FileCounter::new`] takes precedence over the total.
+    pub countdown: Option<i64>,
+    /// Commands to run after each change is saved
+    pub hooks: Hooks,
+    /// Keep running totals of the amounts added to and subtracted from each counter
+    /// (saved in the file). Once a file has totals they are always kept.
+    pub totals: bool,
+    /// Write the counters in the Prometheus text format to this file whenever they are
+    /// saved, e.g. for node-exporter's textfile collector
+    pub metrics_file: Option<PathBuf>,
+    /// Step to give the active counter (saved in the file)
+    pub step: Option<i64>,
+    /// Units to give the active counter (saved in the file)
+    pub units: Option<String>,
+    /// Back up the file before every this many changes (see [`backup`](crate::backup))
+    pub backup_every: Option<u32>,
+    /// Back up the file before a change if the last automatic backup is older than this
+    pub backup_interval: Option<Duration>,
+    /// Number of automatic backups to keep (at least one is)
+    pub backup_keep: usize,
+}
+
+/// A set of named counters that persists their values to a text file
+#[allow(clippy::struct_excessive_bools)]
+pub struct FileCounter {
+    path: PathBuf,
+    counters: Vec<Counter>,
+    // Index into `counters` of the counter that operations apply to
+    active: usize,
+    data_sync: bool,
+    journal: Option<Journal>,
+    history: Option<History>,
+    lock: Option<FileLock>,
+    lock_mode: LockMode,
+    bounds: Bounds,
+    stop_at_target: bool,
+    hooks: Hooks,
+    totals: bool,
+    metrics_file: Option<PathBuf>,
+    read_only: bool,
+    backup_every: Option<u32>,
+    backup_interval: Option<Duration>,
+    backup_keep: usize,
+    // Changes since the last automatic backup, saved in the file with `backup_every`
+    changes_since_backup: u32,
+    // Time of the most recent automatic backup, if any
+    last_backup: Option<u64>,
+    // State of the files when they were last read or written, to notice changes made by
+    // something else
+    seen: Cell<Stamps>,
+}
+
+impl FileCounter {
+    /// Open the counter file at `path`, creating it if missing
+    ///
+    /// Counters named in `names` are created if they don't exist, and the first of them
+    /// is made active. `value`, if given, becomes the active counter's value.
+    pub fn new(
+        path: PathBuf,
+        value: Option<i64>,
+        names: &[String],
+        options: &Options,
+    ) -> Result<Self, Error> {
+        // Initial count precedence for the selected counter:
+        //   1) `value` argument
+        //   2) replay of the journal, if enabled and non-empty
+        //   3) contents of file given by `path` argument
+        //   4) 0
+
+        // Resolve symlinks up front so that the rename in persist() replaces the
+        // target file rather than the link
+        let path = resolve(path);
+
+        // Readers don't need to exclude other instances, but do wait for a consistent
+        // file in shared mode
+        let lock_mode = match options.lock_mode {
+            LockMode::Exclusive if options.read_only => LockMode::None,
+            mode => mode,
+        };
+        let lock = match lock_mode {
+            LockMode::None => None,
+            LockMode::Exclusive | LockMode::Shared => Some(FileLock::open(&path)?),
+        };
+        if let (Some(lock), LockMode::Exclusive) = (&lock, lock_mode)
+            && !lock.try_lock()?
+        {
+            return Err(Error::Locked(path));
+        }
+
+        let mut counter = Self {
+            journal: options
+                .journal
+                .then(|| Journal::new(&path, options.data_sync)),
+            history: (options.history_depth > 0)
+                .then(|| History::new(&path, options.data_sync, options.history_depth)),
+            path,
+            counters: Vec::new(),
+            active: 0,
+            data_sync: options.data_sync,
+            lock,
+            lock_mode,
+            bounds: options.bounds,
+            stop_at_target: options.stop_at_target,
+            hooks: options.hooks.clone(),
+            totals: options.totals,
+            metrics_file: options.metrics_file.clone(),
+            read_only: options.read_only,
+            backup_every: options.backup_every,
+            backup_interval: options.backup_interval,
+            backup_keep: options.backup_keep.max(1),
+            changes_since_backup: 0,
+            last_backup: None,
+            seen: Cell::default(),
+        };
+        if counter.backup_interval.is_some() {
+            let backups = backup::list(&counter.path)?;
+            counter.last_backup = backups.iter().find(|b| b.automatic).map(|b| b.time);
+        }
+
+        counter.with_shared_lock(|counter| {
+            let replayed = counter.load(options.overwrite_invalid)?;
+
+            // Create requested counters that don't exist yet
+            let mut created: Vec<&str> = names
+                .iter()
+                .map(String::as_str)
+                .filter(|name| !counter.counters.iter().any(|c| c.name == *name))
+                .collect();
+            if counter.counters.is_empty() && created.is_empty() {
+                created.push(DEFAULT_NAME);
+            }
+            for name in created {
+                counter.counters.push(Counter {
+                    created: Some(journal::now()),
+                    ..Counter::new(name, 0)
+                });
+            }
+            counter.active = names
+                .first()
+                .and_then(|name| counter.counters.iter().position(|c| &c.name == name))
+                .unwrap_or(0);
+            counter.configure_active(options);
+            let bounds = counter.bounds();
+            if bounds.min > bounds.max {
+                let name = counter.counters[counter.active].name.clone();
+                let (min, max) = (bounds.min, bounds.max);
+                return Err(Error::InvalidBounds { name, min, max });
+            }
+
+            // Make sure the journal knows about every counter
+            for i in 0..counter.counters.len() {
+                let Counter { name, count, .. } = &counter.counters[i];
+                if !replayed.iter().any(|(n, _)| n == name) {
+                    let (name, op) = (name.clone(), Op::Snapshot(*count));
+                    counter.record(&name, op)?;
+                }
+            }
+
+            if let Some(value) = value.or(options.countdown) {
+                // Keep the value being replaced
+                if counter.backups_enabled() && counter.counters[counter.active].count != value {
+                    counter.back_up()?;
+                }
+                let bounds = counter.bounds();
+                let Some(outcome) = bounds.apply(i128::from(value)) else {
+                    return Err(Error::rejected(i128::from(value), bounds));
+                };
+                let value = outcome.value();
+                counter.set_count(counter.active, value);
+                counter.record_active(Op::Set(value))?;
+            }
+            counter.persist()
+        })?;
+        Ok(counter)
+    }
+
+    // Apply `op` to the active counter, subject to its bounds (failing if they reject
+    // it), and run the hooks once it is saved. Changes made by others are read first so
+    // that they are not lost.
+    fn update(&mut self, op: Op) -> Result<Outcome, Error> {
+        self.with_shared_lock(|counter| {
+            counter.catch_up()?;
+
+            let before = counter.counters[counter.active].clone();
+            let old = before.count;
+            let target = op.target(old);
+            let bounds = counter.active_bounds();
+            let Some(outcome) = bounds.apply(target) else {
+                return Err(Error::rejected(target, bounds));
+            };
+            let new = outcome.value();
+            counter.back_up_if_due()?;
+            counter.set_count(counter.active, new);
+            // Journal what actually happened if the bounds changed the result
+            match outcome {
+                Outcome::Applied(_) => counter.record_active(op)?,
+                _ => counter.record_active(Op::between(old, new))?,
+            }
+            if let Some(history) = &mut counter.history
+                && new != old
+            {
+                history.push(Change {
+                    name: counter.counters[counter.active].name.clone(),
+                    before: old,
+                    after: new,
+                });
+            }
+            counter.persist()?;
+            let after = &counter.counters[counter.active];
+            counter.hooks.changed(&counter.path, &before, after);
+            Ok(outcome)
+        })
+    }
+
+    // Take a change from the history with `take`, and move its counter from the first
+    // value picked by `values` to the second. Bounds are not applied, as the value was
+    // valid when recorded. If the counter no longer has the first value, something else
+    // has changed it since, and the change is put back with `put_back` and refused
+    // rather than overwriting that.
+    fn step_history(
+        &mut self,
+        take: fn(&mut History) -> Option<Change>,
+        put_back: fn(&mut History) -> Option<Change>,
+        values: fn(&Change) -> (i64, i64),
+    ) -> Result<bool, Error> {
+        self.with_shared_lock(|counter| {
+            counter.catch_up()?;
+
+            let Some(change) = counter.history.as_mut().and_then(take) else {
+                return Ok(false);
+            };
+            let before = counter.counters.iter().find(|c| c.name == change.name);
+            let before = before.map_or_else(|| Counter::new(&change.name, 0), Clone::clone);
+            let (expected, new) = values(&change);
+            let old = before.count;
+            if old != expected {
+                counter.history.as_mut().and_then(put_back);
+                return Err(Error::Changed {
+                    name: change.name,
+                    count: old,
+                });
+            }
+            counter.back_up_if_due()?;
+            counter.merge([(change.name.as_str(), old)]);
+            counter.active = counter
+                .counters
+                .iter()
+                .position(|c| c.name == change.name)
+                .unwrap_or(counter.active);
+            counter.set_count(counter.active, new);
+            counter.record(&change.name, Op::between(old, new))?;
+            counter.persist()?;
+            let after = &counter.counters[counter.active];
+            counter.hooks.changed(&counter.path, &before, after);
+            Ok(true)
+        })
+    }
+
+    // Give the active counter the target, countdown and metadata from `options`
+    fn configure_active(&mut self, options: &Options) {
+        let active = &mut self.counters[self.active];
+        if let Some(target) = options.target {
+            active.target = Some(target);
+        }
+        if let Some(total) = options.countdown {
+            active.total = Some(total);
+        }
+        if let Some(step) = options.step {
+            active.step = Some(step);
+        }
+        if let Some(units) = &options.units {
+            active.units = Some(units.clone());
+        }
+        let Bounds { min, max, .. } = options.bounds;
+        if min != i64::MIN {
+            active.min = Some(min);
+        }
+        if max != i64::MAX {
+            active.max = Some(max);
+        }
+    }
+
+    const fn backups_enabled(&self) -> bool {
+        self.backup_every.is_some() || self.backup_interval.is_some()
+    }
+
+    // Count a change, and back up the file as it is before it when the automatic
+    // backups are due
+    fn back_up_if_due(&mut self) -> Result<(), io::Error> {
+        self.changes_since_backup = self.changes_since_backup.saturating_add(1);
+        let due_by_count = self
+            .backup_every
+            .is_some_and(|n| self.changes_since_backup >= n);
+        let due_by_time = self.backup_interval.is_some_and(|interval| {
+            self.last_backup
+                .is_none_or(|last| journal::now().saturating_sub(last) >= interval.as_secs())
+        });
+        if due_by_count || due_by_time {
+            self.back_up()?;
+        }
+        Ok(())
+    }
+
+    // Make an automatic backup of the file, if there is one
+    fn back_up(&mut self) -> Result<(), io::Error> {
+        if self.read_only || !self.path.exists() {
+            return Ok(());
+        }
+        backup::rotate(&self.path, self.backup_keep)?;
+        self.changes_since_backup = 0;
+        self.last_backup = Some(journal::now());
+        Ok(())
+    }
+
+    /// Replace the counters with `counters`, e.g. those in a backup, keeping the active
+    /// counter selected if it is among them. Does nothing if `counters` is empty.
+    ///
+    /// Running totals are not restored, as they only ever grow: the change to each
+    /// restored value is added to the current totals instead.
+    pub fn restore(&mut self, mut counters: Vec<Counter>) -> Result<(), Error> {
+        if counters.is_empty() {
+            return Ok(());
+        }
+        self.with_shared_lock(|counter| {
+            counter.catch_up()?;
+            // Make the restored values the journal's latest
+            for Counter { name, count, .. } in &counters {
+                counter.record(name, Op::Snapshot(*count))?;
+            }
+            let values: Vec<i64> = counters.iter().map(|c| c.count).collect();
+            for restored in &mut counters {
+                if let Some(current) = counter.counters.iter().find(|c| c.name == restored.name) {
+                    restored.count = current.count;
+                    restored.increased = current.increased;
+                    restored.decreased = current.decreased;
+                    restored.updated = current.updated;
+                }
+            }
+            let active = &counter.counters[counter.active].name;
+            counter.active = counters.iter().position(|c| &c.name == active).unwrap_or(0);
+            counter.counters = counters;
+            for (index, value) in values.into_iter().enumerate() {
+                counter.set_count(index, value);
+            }
+            counter.persist()
+        })
+    }
+
+    /// Path of the counter file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Bounds for changes to the active counter, including any saved with it
+    #[must_use]
+    pub fn bounds(&self) -> Bounds {
+        self.counters[self.active].bounds(self.bounds)
+    }
+
+    /// The commands run on changes
+    #[must_use]
+    pub const fn hooks(&self) -> &Hooks {
+        &self.hooks
+    }
+
+    #[must_use]
+    pub const fn lock_mode(&self) -> LockMode {
+        self.lock_mode
+    }
+
+    // Bounds for changes to the active counter, narrowed to stop at its goal if
+    // requested
+    fn active_bounds(&self) -> Bounds {
+        let mut bounds = self.bounds();
+        let counter = &self.counters[self.active];
+        if let Some(target) = counter.goal()
+            && self.stop_at_target
+            && (bounds.min..=bounds.max).contains(&target)
+        {
+            if counter.count <= target {
+                bounds.max = target;
+            } else {
+                bounds.min = target;
+            }
+        }
+        bounds
+    }
+
+    // Run `f` while holding the per-operation lock when in shared lock mode
+    fn with_shared_lock<T, F>(&mut self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut Self) -> Result<T, Error>,
+    {
+        match (self.lock.take(), self.lock_mode) {
+            (Some(lock), LockMode::Shared) => {
+                let result = lock.lock().map_err(Error::from).and_then(|_guard| f(self));
+                self.lock = Some(lock);
+                result
+            }
+            (lock, _) => {
+                self.lock = lock;
+                f(self)
+            }
+        }
+    }
+
+    // Update counters from stored values, adding any that are new
+    fn merge<'a>(&mut self, stored: impl IntoIterator<Item = (&'a str, i64)>) {
+        for (name, count) in stored {
+            match self.counters.iter_mut().find(|c| c.name == name) {
+                Some(counter) => counter.count = count,
+                None => self.counters.push(Counter::new(name, count)),
+            }
+        }
+    }
+
+    // Set the count of the counter at `index`, noting when it changed and adding the
+    // change to its running totals if they are kept
+    fn set_count(&mut self, index: usize, count: i64) {
+        let totals = self.keeps_totals();
+        let counter = &mut self.counters[index];
+        if count != counter.count {
+            counter.updated = Some(journal::now());
+        }
+        if totals {
+            let delta = i128::from(count) - i128::from(counter.count);
+            let amount = u64::try_from(delta.unsigned_abs()).unwrap_or(u64::MAX);
+            let total = if delta > 0 {
+                &mut counter.increased
+            } else {
+                &mut counter.decreased
+            };
+            *total = total.saturating_add(amount);
+        }
+        counter.count = count;
+    }
+
+    // Running totals are kept when asked for, and from then on once the file has them, so
+    // that they never miss a change
+    fn keeps_totals(&self) -> bool {
+        self.totals
+            || self
+                .counters
+                .iter()
+                .any(|c| c.increased > 0 || c.decreased > 0)
+    }
+
+    // Update counters, including their targets, from the file's contents
+    fn merge_stored(&mut self, stored: Vec<Counter>) {
+        for counter in stored {
+            match self.counters.iter_mut().find(|c| c.name == counter.name) {
+                Some(existing) => *existing = counter,
+                None => self.counters.push(counter),
+            }
+        }
+    }
+
+    // Refresh the in-memory counts and history from storage
+    fn reload(&mut self) -> Result<(), Error> {
+        self.load(false).map(drop)
+    }
+
+    // Reload before a change if the files may have been changed by someone else: always
+    // in shared lock mode (under the lock), otherwise if they look different from when
+    // they were last read or written
+    fn catch_up(&mut self) -> Result<(), Error> {
+        if self.lock_mode != LockMode::Shared && self.stamps() == self.seen.get() {
+            return Ok(());
+        }
+        self.reload()
+    }
+
+    // Read the history, the file and the journal, returning the values replayed from the
+    // journal. The journal's counts are the most recent unless the file was saved without
+    // it since (in which case the journal is brought up to date). Targets only live in
+    // the file, which is not needed (and may be invalid) when the journal has entries.
+    fn load(&mut self, overwrite_invalid: bool) -> Result<Vec<(String, i64)>> {
+        // Taken first, so that a change made while reading is noticed later
+        let stamps = self.stamps();
+        self.load_history()?;
+        let replayed = self.replay()?;
+        let replayed = match self.read_file()? {
+            Some(stored) => {
+                // The file is newer if it was saved without the journal since the journal's
+                // last entry
+                let journal_current = self.journal.as_ref().is_none_or(|journal| {
+                    stored
+                        .journal_entries
+                        .is_some_and(|entries| entries <= journal.recorded())
+                });
+                self.changes_since_backup = stored.changes_since_backup;
+                self.merge_stored(stored.counters);
+                if journal_current {
+                    replayed
+                } else {
+                    self.update_journal(replayed)?
+                }
+            }
+            None if overwrite_invalid || !replayed.is_empty() => replayed,
+            None => return Err(Error::Corrupt(self.path.clone())),
+        };
+        self.merge(replayed.iter().map(|(name, count)| (name.as_str(), *count)));
+        self.seen.set(stamps);
+        Ok(replayed)
+    }
+
+    // Record a snapshot of every counter whose value in the file differs from `replayed`,
+    // the values from the journal, returning the values the journal now holds
+    fn update_journal(
+        &mut self,
+        mut replayed: Vec<(String, i64)>,
+    ) -> Result<Vec<(String, i64)>, io::Error> {
+        for i in 0..self.counters.len() {
+            let Counter { name, count, .. } = &self.counters[i];
+            let (name, count) = (name.clone(), *count);
+            match replayed.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, value)) if *value == count => continue,
+                Some((_, value)) => *value = count,
+                None => replayed.push((name.clone(), count)),
+            }
+            self.record(&name, Op::Snapshot(count))?;
+        }
+        Ok(replayed)
+    }
+
+    fn load_history(&mut self) -> Result<(), io::Error> {
+        self.history.as_mut().map_or(Ok(()), History::load)
+    }
+
+    fn replay(&mut self) -> Result<Vec<(String, i64)>, io::Error> {
+        self.journal
+            .as_mut()
+            .map_or(Ok(Vec::new()), Journal::replay)
+    }
+
+    // Parse the counters stored in the file, or None if it holds something other than
+    // counters. A missing or empty file holds no counters.
+    fn read_file(&self) -> Result<Option<Stored>, io::Error> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        // Text that isn't UTF-8 can't be counters either
+        Ok(String::from_utf8(contents)
+            .ok()
+            .and_then(|contents| parse_file(&contents)))
+    }
+
+    // Append `op` on the counter called `name` to the journal (if enabled). This happens
+    // before the counter file is rewritten so that the journal is never behind the file.
+    fn record(&mut self, name: &str, op: Op) -> Result<(), io::Error> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.journal
+            .as_mut()
+            .map_or(Ok(()), |journal| journal.record(name, op))
+    }
+
+    fn record_active(&mut self, op: Op) -> Result<(), io::Error> {
+        let name = self.counters[self.active].name.clone();
+        self.record(&name, op)
+    }
+
+    /// Every change recorded in the journal, oldest first (empty if the journal is not
+    /// enabled)
+    pub fn journal_entries(&self) -> Result<Vec<Entry>> {
+        Ok(self
+            .journal
+            .as_ref()
+            .map_or(Ok(Vec::new()), Journal::entries)?)
+    }
+
+    /// Trim the journal down to a snapshot of the current values
+    pub fn compact(&mut self) -> Result<(), Error> {
+        let counters = self.counters.iter().map(|c| (c.name.as_str(), c.count));
+        if let Some(journal) = &mut self.journal {
+            journal.compact(counters)?;
+            // The file records how many entries the journal has
+            self.persist()?;
+        }
+        Ok(())
+    }
+
+    /// Write the counters (and undo history) to storage. Every change is saved as it is
+    /// made, so this is only needed to retry after a failure.
+    pub fn persist(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Ok(());
+        }
+        write_atomic(
+            &self.path,
+            format_counters(
+                &self.counters,
+                self.journal.as_ref().map(Journal::recorded),
+                self.backup_every.map(|_| self.changes_since_backup),
+            )
+            .as_bytes(),
+            self.data_sync,
+        )?;
+        if let Some(history) = &self.history {
+            history.save()?;
+        }
+        if let Some(metrics_file) = &self.metrics_file {
+            let metrics = metrics::render(&self.counters, &self.path);
+            write_atomic(metrics_file, metrics.as_bytes(), self.data_sync)?;
+        }
+        self.seen.set(self.stamps());
+        Ok(())
+    }
+
+    /// Re-read the counters if their file (or journal) has been changed by something else
+    /// since they were last read or written, e.g. by a script or another instance.
+    /// Returns whether they were.
+    pub fn refresh(&mut self) -> Result<bool, Error> {
+        if self.stamps() == self.seen.get() {
+            return Ok(false);
+        }
+        self.with_shared_lock(Self::reload)?;
+        Ok(true)
+    }
+
+    fn stamps(&self) -> Stamps {
+        Stamps {
+            file: Stamp::of(&self.path),
+            journal: self.journal.as_ref().and_then(|j| Stamp::of(j.path())),
+        }
+    }
+}
+
+// The state of the counter file and journal, to tell whether they have changed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Stamps {
+    file: Option<Stamp>,
+    journal: Option<Stamp>,
+}
+
+// Modification time, size and (on Unix) inode of a file. Saving replaces the file, so
+// the inode changes even if the time and size don't.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    inode: u64,
+}
+
+impl Stamp {
+    // None if the file doesn't exist (or can't be read)
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            inode,
+        })
+    }
+}
+
+impl CounterStore for FileCounter {
+    fn counters(&self) -> &[Counter] {
+        &self.counters
+    }
+
+    fn active_index(&self) -> usize {
+        self.active
+    }
+
+    fn select(&mut self, index: usize) {
+        if index < self.counters.len() {
+            self.active = index;
+        }
+    }
+
+    fn increment(&mut self, n: i64) -> Result<Outcome, Error> {
+        self.update(Op::Increment(n))
+    }
+
+    fn decrement(&mut self, n: i64) -> Result<Outcome, Error> {
+        self.update(Op::Decrement(n))
+    }
+
+    fn set(&mut self, value: i64) -> Result<Outcome, Error> {
+        self.update(Op::Set(value))
+    }
+
+    fn undo(&mut self) -> Result<bool, Error> {
+        self.step_history(History::undo, History::redo, |change| {
+            (change.after, change.before)
+        })
+    }
+
+    fn redo(&mut self) -> Result<bool, Error> {
+        self.step_history(History::redo, History::undo, |change| {
+            (change.before, change.after)
+        })
+    }
+}
+
+/// Version of the counter file format written by this library. Files without a version
+/// are from older releases, and are read as before and rewritten in the current format.
+pub const FORMAT_VERSION: i64 = 2;
+
+// Counter files start with the format version, followed by a table per counter:
+//
+//   version = 2
+//   journal-entries = 14              # with the journal: the entries it had when saved
+//   changes-since-backup = 3          # with --backup-every: changes since the last one
+//
+//   [pass]
+//   value = 3
+//   target = 500                      # optional
+//   total = 20                        # optional, for a countdown
+//   increased = 9                     # optional running totals
+//   decreased = 6
+//   step = 5                          # optional metadata
+//   min = 0
+//   max = 1000
+//   units = "laps"
+//   created = "2024-05-06T07:08:09Z"
+//   updated = "2024-05-06T08:00:00Z"
+//
+// Older files hold a single integer (the default counter), or the tables without a
+// version.
+pub fn parse_counters(contents: &str) -> Option<Vec<Counter>> {
+    parse_file(contents).map(|stored| stored.counters)
+}
+
+// The contents of a counter file
+struct Stored {
+    counters: Vec<Counter>,
+    // Number of journal entries the values include, if saved with the journal
+    journal_entries: Option<u64>,
+    // Number of changes made since the last automatic backup
+    changes_since_backup: u32,
+}
+
+fn parse_file(contents: &str) -> Option<Stored> {
+    let stored = |counters| Stored {
+        counters,
+        journal_entries: None,
+        changes_since_backup: 0,
+    };
+    if contents.is_empty() {
+        return Some(stored(Vec::new()));
+    }
+    let line = contents.lines().next().unwrap_or_default();
+    if let Ok(count) = line.trim_end().parse::<i64>() {
+        return Some(stored(vec![Counter::new(DEFAULT_NAME, count)]));
+    }
+
+    let document = toml_lite::parse(contents).ok()?;
+    let root = document.root();
+    let version = match root.get("version") {
+        Some(version) => version.as_integer()?,
+        None => 1,
+    };
+    let journal_entries = match root.get("journal-entries") {
+        Some(entries) => Some(u64::try_from(entries.as_integer()?).ok()?),
+        None => None,
+    };
+    let changes_since_backup = match root.get("changes-since-backup") {
+        Some(changes) => u32::try_from(changes.as_integer()?).ok()?,
+        None => 0,
+    };
+    if !(1..=FORMAT_VERSION).contains(&version)
+        || root.entries.iter().any(|(key, _)| {
+            !matches!(
+                key.as_str(),
+                "version" | "journal-entries" | "changes-since-backup"
+            )
+        })
+    {
+        return None;
+    }
+    let counters = document
+        .named_tables()
+        .map(parse_counter)
+        .collect::<Option<Vec<_>>>()?;
+    if counters.is_empty() {
+        return None;
+    }
+    Some(Stored {
+        counters,
+        journal_entries,
+        changes_since_backup,
+    })
+}
+
+// A counter from its table, or None if a value is missing or of the wrong type
+fn parse_counter(table: &Table) -> Option<Counter> {
+    // Outer None if present but not of the right type
+    let optional = |key| {
+        table
+            .get(key)
+            .map_or(Some(None), |value| value.as_integer().map(Some))
+    };
+    let text = |key| {
+        table
+            .get(key)
+            .map_or(Some(None), |value| value.as_str().map(Some))
+    };
+    let time = |key| text(key)?.map_or(Some(None), |time| stats::parse_time(time).map(Some));
+    let running_total = |key| optional(key)?.map_or(Some(0), |total| u64::try_from(total).ok());
+
+    let count = table.get("value").and_then(Value::as_integer)?;
+    if !is_valid_name(&table.name) {
+        return None;
+    }
+    let (min, max) = (optional("min")?, optional("max")?);
+    if let (Some(min), Some(max)) = (min, max)
+        && min > max
+    {
+        return None;
+    }
+    Some(Counter {
+        name: table.name.clone(),
+        count,
+        target: optional("target")?,
+        total: optional("total")?,
+        increased: running_total("increased")?,
+        decreased: running_total("decreased")?,
+        step: match optional("step")? {
+            Some(step) if step < 1 => return None,
+            step => step,
+        },
+        min,
+        max,
+        units: text("units")?.map(str::to_string),
+        created: time("created")?,
+        updated: time("updated")?,
+    })
+}
+
+fn format_counters(
+    counters: &[Counter],
+    journal_entries: Option<u64>,
+    changes_since_backup: Option<u32>,
+) -> String {
+    let mut document = Document::default();
+    document.tables[0].insert("version", Value::Integer(FORMAT_VERSION));
+    if let Some(entries) = journal_entries {
+        let entries = i64::try_from(entries).unwrap_or(i64::MAX);
+        document.tables[0].insert("journal-entries", Value::Integer(entries));
+    }
+    if let Some(changes) = changes_since_backup.filter(|&changes| changes > 0) {
+        document.tables[0].insert("changes-since-backup", Value::Integer(changes.into()));
+    }
+    for counter in counters {
+        let mut table = Table::new(&counter.name);
+        table.insert("value", Value::Integer(counter.count));
+        let optional = [
+            ("target", counter.target),
+            ("total", counter.total),
+            ("increased", running_total(counter.increased)),
+            ("decreased", running_total(counter.decreased)),
+            ("step", counter.step),
+            ("min", counter.min),
+            ("max", counter.max),
+        ];
+        for (key, value) in optional {
+            if let Some(value) = value {
+                table.insert(key, Value::Integer(value));
+            }
+        }
+        if let Some(units) = &counter.units {
+            table.insert("units", Value::String(units.clone()));
+        }
+        for (key, time) in [("created", counter.created), ("updated", counter.updated)] {
+            if let Some(time) = time {
+                table.insert(key, Value::String(stats::format_time(time)));
+            }
+        }
+        document.push_table(table);
+    }
+    document.to_string()
+}
+
+// A running total as written to the file, where zero is left out
+fn running_total(total: u64) -> Option<i64> {
+    (total > 0).then(|| i64::try_from(total).unwrap_or(i64::MAX))
+}
+
+// Replace the contents of `path` without ever exposing a truncated file: the data is
+// written to a temporary sibling which is then renamed over the target. When `sync` is
+// set, both the data and the directory entry are flushed to disk before returning.
+pub fn write_atomic(path: &Path, contents: &[u8], sync: bool) -> Result<(), io::Error> {
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let result = (|| {
+        let mut tmp = File::create(&tmp_path)?;
+        // Keep the permissions of the file we are replacing
+        if let Ok(metadata) = fs::metadata(path) {
+            tmp.set_permissions(metadata.permissions())?;
+        }
+        tmp.write_all(contents)?;
+        tmp.flush()?;
+        if sync {
+            tmp.sync_data()?;
+        }
+        fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result?;
+
+    if sync {
+        sync_parent_dir(path)?;
+    }
+    Ok(())
+}
+
+// Flush the directory entry of `path` so that a rename survives a power loss
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<(), io::Error> {
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+const fn sync_parent_dir(_path: &Path) -> Result<(), io::Error> {
+    Ok(())
+}
+
+// The absolute path of `path` with symlinks resolved. A file that doesn't exist yet is
+// resolved through its directory, so that the path is the same once it does (it names
+// the counters in the metrics, for one).
+fn resolve(path: PathBuf) -> PathBuf {
+    if let Ok(resolved) = fs::canonicalize(&path) {
+        return resolved;
+    }
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    match (fs::canonicalize(dir), path.file_name()) {
+        (Ok(dir), Some(name)) => dir.join(name),
+        _ => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let counters = vec![
+            Counter {
+                target: Some(500),
+                increased: 9,
+                decreased: 6,
+                step: Some(5),
+                min: Some(i64::MIN),
+                max: Some(1000),
+                units: Some("laps \"fast\"\n".to_string()),
+                created: Some(1_714_979_289),
+                updated: Some(1_714_982_400),
+                ..Counter::new("pass", 3)
+            },
+            Counter {
+                total: Some(20),
+                ..Counter::new("count-down_2", i64::MIN)
+            },
+        ];
+        let text = format_counters(&counters, Some(14), Some(3));
+        let stored = parse_file(&text).unwrap();
+        assert_eq!(stored.counters, counters);
+        assert_eq!(stored.journal_entries, Some(14));
+        assert_eq!(stored.changes_since_backup, 3);
+
+        let stored = parse_file(&format_counters(&counters, None, None)).unwrap();
+        assert_eq!(stored.journal_entries, None);
+        assert_eq!(stored.changes_since_backup, 0);
+    }
+
+    #[test]
+    fn older_formats() {
+        let counters = parse_counters("42\n").unwrap();
+        assert_eq!(counters, [Counter::new(DEFAULT_NAME, 42)]);
+        let counters = parse_counters("[a]\r\nvalue = 1\r\n").unwrap();
+        assert_eq!(counters, [Counter::new("a", 1)]);
+        assert_eq!(parse_counters(""), Some(Vec::new()));
+    }
+
+    #[test]
+    fn invalid_files() {
+        for invalid in [
+            "hello",
+            "version = 2\n",
+            "version = 3\n[a]\nvalue = 1\n",
+            "other = 1\n[a]\nvalue = 1\n",
+            "[a]\nvalue = \"1\"\n",
+            "[a]\ntarget = 1\n",
+            "[a]\nvalue = 1\nstep = 0\n",
+            "[a]\nvalue = 1\nincreased = -1\n",
+            "[a]\nvalue = 1\nmin = 10\nmax = 5\n",
+            "[a]\nvalue = 1\ncreated = \"yesterday\"\n",
+            "[\"not bare\"]\nvalue = 1\n",
+        ] {
+            assert!(parse_counters(invalid).is_none(), "{invalid}");
+        }
+    }
+}
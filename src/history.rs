@@ -0,0 +1,115 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::write_atomic;
+
+/// A change to one counter that can be undone or redone
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    pub name: String,
+    pub before: i64,
+    pub after: i64,
+}
+
+/// Undo and redo stacks, stored in `<path>.history` next to the counter file so that
+/// they survive a restart
+///
+/// Each line has the form `undo|redo <counter name> <before> <after>`, oldest first.
+pub struct History {
+    path: PathBuf,
+    data_sync: bool,
+    depth: usize,
+    undo: Vec<Change>,
+    redo: Vec<Change>,
+}
+
+impl History {
+    /// History belonging to the counter file `counter_path`, keeping at most `depth`
+    /// undo steps
+    #[must_use]
+    pub fn new(counter_path: &Path, data_sync: bool, depth: usize) -> Self {
+        let mut name = OsString::from(counter_path.file_name().unwrap_or_default());
+        name.push(".history");
+        Self {
+            path: counter_path.with_file_name(name),
+            data_sync,
+            depth,
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    /// Replace the in-memory stacks with the stored ones. Lines that cannot be parsed
+    /// are skipped.
+    pub fn load(&mut self) -> Result<(), io::Error> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        self.undo.clear();
+        self.redo.clear();
+        for line in contents.lines() {
+            let mut fields = line.split_ascii_whitespace();
+            let (Some(stack), Some(name), Some(before), Some(after)) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let (Ok(before), Ok(after)) = (before.parse(), after.parse()) else {
+                continue;
+            };
+            let change = Change {
+                name: name.to_string(),
+                before,
+                after,
+            };
+            match stack {
+                "undo" => self.undo.push(change),
+                "redo" => self.redo.push(change),
+                _ => {}
+            }
+        }
+        self.trim();
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), io::Error> {
+        let line = |stack, c: &Change| format!("{stack} {} {} {}\n", c.name, c.before, c.after);
+        let contents: String = (self.undo.iter().map(|c| line("undo", c)))
+            .chain(self.redo.iter().map(|c| line("redo", c)))
+            .collect();
+        write_atomic(&self.path, contents.as_bytes(), self.data_sync)
+    }
+
+    /// Record a new change, which makes anything previously undone impossible to redo
+    pub fn push(&mut self, change: Change) {
+        self.undo.push(change);
+        self.redo.clear();
+        self.trim();
+    }
+
+    /// The most recent change, which moves to the redo stack
+    pub fn undo(&mut self) -> Option<Change> {
+        let change = self.undo.pop()?;
+        self.redo.push(change.clone());
+        Some(change)
+    }
+
+    /// The most recently undone change, which moves back to the undo stack
+    pub fn redo(&mut self) -> Option<Change> {
+        let change = self.redo.pop()?;
+        self.undo.push(change.clone());
+        Some(change)
+    }
+
+    fn trim(&mut self) {
+        let excess = self.undo.len().saturating_sub(self.depth);
+        self.undo.drain(..excess);
+        let excess = self.redo.len().saturating_sub(self.depth);
+        self.redo.drain(..excess);
+    }
+}
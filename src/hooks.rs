@@ -0,0 +1,172 @@
+//! Commands run when counters change, e.g. to wire a counter into other scripts
+//!
+//! Commands run with the system shell in the background, so that a slow one never holds
+//! up counting, and are told about the change through environment variables:
+//!
+//! - `COUNTER_NAME`, `COUNTER_PATH`: the counter and its file
+//! - `COUNTER_OLD`, `COUNTER_NEW`, `COUNTER_DELTA`: the value before and after the change,
+//!   and the difference
+//! - `COUNTER_VALUE`: the current value (the same as `COUNTER_NEW`)
+//! - `COUNTER_TARGET`: the value the counter is working toward, if any (zero for a
+//!   countdown)
+
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::Counter;
+
+// How often a running command is checked for having finished
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Commands to run on counter events. Each is optional.
+#[derive(Clone, Debug, Default)]
+pub struct Hooks {
+    /// Run after every change
+    pub on_change: Option<String>,
+    /// Run after a change that increases a count
+    pub on_increment: Option<String>,
+    /// Run after a change that decreases a count
+    pub on_decrement: Option<String>,
+    /// Run when a count reaches its target, or a countdown reaches zero
+    pub on_target: Option<String>,
+    /// Run by [`Hooks::exit`] when a program using the counters is done with them
+    pub on_exit: Option<String>,
+    /// How long a command may run before it is killed (no limit if None)
+    pub timeout: Option<Duration>,
+    // Threads waiting for commands started by changes, shared between clones
+    pending: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl Hooks {
+    /// Run the commands for a change of a counter in `path` from `before` to `after`.
+    /// Nothing is run if the value didn't change.
+    pub fn changed(&self, path: &Path, before: &Counter, after: &Counter) {
+        if before.count == after.count {
+            return;
+        }
+        let delta = i128::from(after.count) - i128::from(before.count);
+        let mut env = env(path, after);
+        env.extend([
+            ("COUNTER_OLD", before.count.to_string()),
+            ("COUNTER_NEW", after.count.to_string()),
+            ("COUNTER_DELTA", delta.to_string()),
+        ]);
+
+        let direction = if delta > 0 {
+            &self.on_increment
+        } else {
+            &self.on_decrement
+        };
+        let reached = (after.at_target() && !before.at_target()).then_some(&self.on_target);
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        pending.retain(|handle| !handle.is_finished());
+        for command in [Some(&self.on_change), Some(direction), reached]
+            .into_iter()
+            .flatten()
+            .flatten()
+        {
+            pending.extend(spawn(command, &env, self.timeout));
+        }
+    }
+
+    /// Wait for the commands run by changes that are still running, so that those
+    /// outliving the timeout are killed rather than left behind when the program exits.
+    /// Without a timeout they are left to finish on their own.
+    pub fn finish(&self) {
+        if self.timeout.is_none() {
+            return;
+        }
+        let pending =
+            std::mem::take(&mut *self.pending.lock().unwrap_or_else(PoisonError::into_inner));
+        for handle in pending {
+            _ = handle.join();
+        }
+    }
+
+    /// Run the exit command for `counter` in `path`, waiting (up to the timeout) for it to
+    /// finish so that it isn't cut short by the program exiting
+    pub fn exit(&self, path: &Path, counter: &Counter) {
+        if let Some(command) = &self.on_exit
+            && let Some(handle) = spawn(command, &env(path, counter), self.timeout)
+        {
+            _ = handle.join();
+        }
+    }
+}
+
+// Environment describing `counter`'s current state
+fn env(path: &Path, counter: &Counter) -> Vec<(&'static str, String)> {
+    let mut env = vec![
+        ("COUNTER_NAME", counter.name.clone()),
+        ("COUNTER_PATH", path.display().to_string()),
+        ("COUNTER_VALUE", counter.count.to_string()),
+    ];
+    if let Some(goal) = counter.goal() {
+        env.push(("COUNTER_TARGET", goal.to_string()));
+    }
+    env
+}
+
+// Start `command` with the system shell, with `env` added to its environment, and wait
+// for it on a background thread, killing it if it runs for longer than `timeout`. Its
+// output is discarded so that it doesn't disturb the display. On Unix it gets a process
+// group of its own, so that whatever it starts can be killed with it. Returns the
+// waiting thread, or None if the command couldn't be started.
+fn spawn(
+    command: &str,
+    env: &[(&str, String)],
+    timeout: Option<Duration>,
+) -> Option<JoinHandle<()>> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell
+        .arg(command)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut shell, 0);
+    let child = shell.spawn().ok()?;
+    Some(thread::spawn(move || wait(child, timeout)))
+}
+
+// Reap `child`, killing it first if it outlives `timeout`
+fn wait(mut child: Child, timeout: Option<Duration>) {
+    let Some(timeout) = timeout else {
+        _ = child.wait();
+        return;
+    };
+    let deadline = Instant::now() + timeout;
+    while matches!(child.try_wait(), Ok(None)) {
+        if Instant::now() >= deadline {
+            kill(&mut child);
+            _ = child.wait();
+            return;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+// Kill `child`, and on Unix the rest of its process group: the shell may have started
+// the command as a process of its own, which would otherwise keep running
+fn kill(child: &mut Child) {
+    if cfg!(unix) {
+        _ = Command::new("kill")
+            .args(["-KILL", "--", &format!("-{}", child.id())])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+    _ = child.kill();
+}
@@ -0,0 +1,223 @@
+use std::ffi::OsString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{DEFAULT_NAME, write_atomic};
+
+/// A single change recorded in the journal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    /// Absolute value recorded by a compaction (or when the journal is first created)
+    Snapshot(i64),
+    /// Absolute value chosen by the user
+    Set(i64),
+    Increment(i64),
+    Decrement(i64),
+}
+
+impl Op {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Snapshot(_) => "snapshot",
+            Self::Set(_) => "set",
+            Self::Increment(_) => "inc",
+            Self::Decrement(_) => "dec",
+        }
+    }
+
+    const fn operand(self) -> i64 {
+        match self {
+            Self::Snapshot(n) | Self::Set(n) | Self::Increment(n) | Self::Decrement(n) => n,
+        }
+    }
+
+    fn parse(name: &str, operand: &str) -> Option<Self> {
+        let n = operand.parse().ok()?;
+        match name {
+            "snapshot" => Some(Self::Snapshot(n)),
+            "set" => Some(Self::Set(n)),
+            "inc" => Some(Self::Increment(n)),
+            "dec" => Some(Self::Decrement(n)),
+            _ => None,
+        }
+    }
+
+    /// Value the counter would have after this operation, before any bounds apply
+    #[must_use]
+    pub fn target(self, value: i64) -> i128 {
+        match self {
+            Self::Snapshot(n) | Self::Set(n) => i128::from(n),
+            Self::Increment(n) => i128::from(value) + i128::from(n),
+            Self::Decrement(n) => i128::from(value) - i128::from(n),
+        }
+    }
+
+    /// The increment or decrement that takes the counter from `old` to `new`
+    #[must_use]
+    pub fn between(old: i64, new: i64) -> Self {
+        i64::try_from(i128::from(new) - i128::from(old)).map_or(Self::Set(new), |delta| {
+            if delta < 0 {
+                delta.checked_neg().map_or(Self::Set(new), Self::Decrement)
+            } else {
+                Self::Increment(delta)
+            }
+        })
+    }
+
+    /// Value of the counter after applying this operation to `value`
+    #[must_use]
+    pub const fn apply(self, value: i64) -> i64 {
+        match self {
+            Self::Snapshot(n) | Self::Set(n) => n,
+            Self::Increment(n) => value.saturating_add(n),
+            Self::Decrement(n) => value.saturating_sub(n),
+        }
+    }
+}
+
+/// An operation read back from the journal
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// Unix time in seconds
+    pub timestamp: u64,
+    pub op: Op,
+    pub name: String,
+}
+
+/// Append-only log of timestamped counter operations, stored next to the counter file
+///
+/// Each line has the form `<unix seconds> <op> <operand> [<counter name>]`, where a
+/// missing name refers to the default counter. Lines that cannot be parsed (e.g. a torn
+/// write at the end of the file after a crash) are skipped on replay.
+pub struct Journal {
+    path: PathBuf,
+    data_sync: bool,
+    // Set when the file ends in a partial line which must be terminated before appending
+    needs_newline: bool,
+    // Number of entries as of the last replay, plus those recorded since
+    recorded: u64,
+}
+
+impl Journal {
+    /// Journal belonging to the counter file `counter_path`
+    #[must_use]
+    pub fn new(counter_path: &Path, data_sync: bool) -> Self {
+        let mut name = OsString::from(counter_path.file_name().unwrap_or_default());
+        name.push(".log");
+        Self {
+            path: counter_path.with_file_name(name),
+            data_sync,
+            needs_newline: false,
+            recorded: 0,
+        }
+    }
+
+    /// Number of entries in the journal as of the last [`replay`](Self::replay), plus
+    /// those recorded since
+    #[must_use]
+    pub const fn recorded(&self) -> u64 {
+        self.recorded
+    }
+
+    /// Path of the journal file
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Reconstruct the counter values by replaying the journal, in order of first
+    /// appearance (empty if it has no entries)
+    pub fn replay(&mut self) -> Result<Vec<(String, i64)>, io::Error> {
+        let contents = self.read()?;
+        self.needs_newline = !contents.is_empty() && !contents.ends_with('\n');
+
+        let mut values: Vec<(String, i64)> = Vec::new();
+        self.recorded = 0;
+        for (_, op, name) in contents.lines().filter_map(parse_line) {
+            self.recorded += 1;
+            match values.iter_mut().find(|(n, _)| n == name) {
+                Some((_, value)) => *value = op.apply(*value),
+                None => values.push((name.to_string(), op.apply(0))),
+            }
+        }
+        Ok(values)
+    }
+
+    /// All entries, oldest first, skipping lines that cannot be parsed
+    pub fn entries(&self) -> Result<Vec<Entry>, io::Error> {
+        let contents = self.read()?;
+        let entries = contents.lines().filter_map(parse_line);
+        Ok(entries
+            .map(|(timestamp, op, name)| Entry {
+                timestamp,
+                op,
+                name: name.to_string(),
+            })
+            .collect())
+    }
+
+    // The journal's contents; empty if it doesn't exist yet
+    fn read(&self) -> Result<String, io::Error> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => Ok(contents),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Append an operation on the counter called `name` to the journal
+    pub fn record(&mut self, name: &str, op: Op) -> Result<(), io::Error> {
+        let separator = if self.needs_newline { "\n" } else { "" };
+        let line = format!("{separator}{}\n", format_line(now(), op, name));
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)?;
+        file.write_all(line.as_bytes())?;
+        file.flush()?;
+        if self.data_sync {
+            file.sync_data()?;
+        }
+        self.needs_newline = false;
+        self.recorded += 1;
+        Ok(())
+    }
+
+    /// Replace all entries with a snapshot of each counter's current value
+    pub fn compact<'a>(
+        &mut self,
+        counters: impl IntoIterator<Item = (&'a str, i64)>,
+    ) -> Result<(), io::Error> {
+        let timestamp = now();
+        let lines: Vec<String> = counters
+            .into_iter()
+            .map(|(name, value)| format_line(timestamp, Op::Snapshot(value), name) + "\n")
+            .collect();
+        write_atomic(&self.path, lines.concat().as_bytes(), self.data_sync)?;
+        self.needs_newline = false;
+        self.recorded = lines.len() as u64;
+        Ok(())
+    }
+}
+
+fn format_line(timestamp: u64, op: Op, name: &str) -> String {
+    format!("{timestamp} {} {} {name}", op.name(), op.operand())
+}
+
+fn parse_line(line: &str) -> Option<(u64, Op, &str)> {
+    let mut fields = line.split_ascii_whitespace();
+    let timestamp = fields.next()?.parse().ok()?;
+    let op = Op::parse(fields.next()?, fields.next()?)?;
+    let name = fields.next().unwrap_or(DEFAULT_NAME);
+    Some((timestamp, op, name))
+}
+
+// The current time as a Unix timestamp
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
@@ -0,0 +1,195 @@
+//! File-backed tally counters
+//!
+//! A counter file holds one or more named counters. [`FileCounter`] opens one, applies
+//! changes subject to [`Bounds`], and saves every change atomically, optionally with a
+//! [`journal`], an undo [`history`], [locking](lock) against other instances, [`hooks`]
+//! run on every change and rotating [backups](backup). Code that only needs to count
+//! can use the [`CounterStore`] trait.
+//!
+//! # Errors
+//!
+//! Operations fail with [`Error`], which tells I/O failures apart from a file that holds
+//! something other than counters (see [`Options::overwrite_invalid`]), a file locked by
+//! another instance, and changes rejected by the bounds.
+
+pub mod backup;
+pub mod bounds;
+mod error;
+mod file_counter;
+pub mod history;
+pub mod hooks;
+pub mod journal;
+pub mod lock;
+pub mod metrics;
+pub mod stats;
+pub mod toml_lite;
+
+pub use crate::bounds::{Bounds, Outcome, Policy};
+pub use crate::error::{Error, Result};
+pub use crate::file_counter::{FORMAT_VERSION, FileCounter, Options};
+pub use crate::hooks::Hooks;
+pub use crate::lock::LockMode;
+
+pub(crate) use crate::file_counter::{parse_counters, write_atomic};
+
+/// Name of the counter in files that hold a single unnamed count
+pub const DEFAULT_NAME: &str = "count";
+
+/// A named count
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Counter {
+    pub name: String,
+    pub count: i64,
+    /// Value the count is working toward
+    pub target: Option<i64>,
+    /// Starting value of a countdown. A countdown counts the items remaining, and is
+    /// complete when it reaches zero; its `target` is not used.
+    pub total: Option<i64>,
+    /// Running total of the amounts added to the count, if kept (see
+    /// [`Options::totals`])
+    pub increased: u64,
+    /// Running total of the amounts subtracted from the count
+    pub decreased: u64,
+    /// Amount the count usually changes by, if not the default of one
+    pub step: Option<i64>,
+    /// Lowest value allowed, in addition to [`Options::bounds`]
+    pub min: Option<i64>,
+    /// Highest value allowed, in addition to [`Options::bounds`]
+    pub max: Option<i64>,
+    /// What is being counted, e.g. `laps`
+    pub units: Option<String>,
+    /// When the counter was created, as a Unix timestamp (unknown for counters from
+    /// older files)
+    pub created: Option<u64>,
+    /// When the count last changed, as a Unix timestamp
+    pub updated: Option<u64>,
+}
+
+impl Counter {
+    /// Counter without a target
+    #[must_use]
+    pub fn new(name: &str, count: i64) -> Self {
+        Self {
+            name: name.to_string(),
+            count,
+            target: None,
+            total: None,
+            increased: 0,
+            decreased: 0,
+            step: None,
+            min: None,
+            max: None,
+            units: None,
+            created: None,
+            updated: None,
+        }
+    }
+
+    /// The bounds for changes to the counter: `bounds` narrowed by its own limits
+    #[must_use]
+    pub fn bounds(&self, bounds: Bounds) -> Bounds {
+        Bounds {
+            min: self.min.map_or(bounds.min, |min| min.max(bounds.min)),
+            max: self.max.map_or(bounds.max, |max| max.min(bounds.max)),
+            ..bounds
+        }
+    }
+
+    /// Whether the counter is a countdown
+    #[must_use]
+    pub const fn is_countdown(&self) -> bool {
+        self.total.is_some()
+    }
+
+    /// Value the count is working toward: zero for a countdown, otherwise its target
+    #[must_use]
+    pub const fn goal(&self) -> Option<i64> {
+        if self.is_countdown() {
+            Some(0)
+        } else {
+            self.target
+        }
+    }
+
+    /// How far the count has come toward its goal, as a fraction of the distance from
+    /// zero, or from the total for a countdown (1.0 or more once reached)
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)] // a fraction doesn't need more precision
+    pub fn progress(&self) -> Option<f64> {
+        let (done, whole) = match self.total {
+            Some(total) => (
+                i128::from(total) - i128::from(self.count),
+                i128::from(total),
+            ),
+            None => (i128::from(self.count), i128::from(self.target?)),
+        };
+        match whole {
+            0 => Some(if done == 0 { 1.0 } else { 0.0 }),
+            whole => Some(done as f64 / whole as f64),
+        }
+    }
+
+    /// Whether the count has reached its goal
+    #[must_use]
+    pub fn at_target(&self) -> bool {
+        self.progress().is_some_and(|progress| progress >= 1.0)
+    }
+}
+
+/// Whether `name` can be used as a counter name: letters, digits, `-` and `_` only, so
+/// that it can appear unquoted in counter files and the journal
+#[must_use]
+pub fn is_valid_name(name: &str) -> bool {
+    toml_lite::is_bare_key(name)
+}
+
+/// A set of counters, one of which is active, that keeps its values somewhere
+///
+/// Changes apply to the active counter and are saved before the methods return.
+pub trait CounterStore {
+    /// All counters, in storage order (never empty)
+    fn counters(&self) -> &[Counter];
+
+    /// Index into [`counters`](Self::counters) of the active counter
+    fn active_index(&self) -> usize;
+
+    /// Make the counter at `index` active (ignored if out of range)
+    fn select(&mut self, index: usize);
+
+    /// Add `n` to the active counter. Fails with [`Error::OutOfRange`] or
+    /// [`Error::Overflow`], leaving the count unchanged, if the bounds reject the change.
+    fn increment(&mut self, n: i64) -> Result<Outcome>;
+
+    /// Subtract `n` from the active counter
+    fn decrement(&mut self, n: i64) -> Result<Outcome>;
+
+    /// Set the active counter to `value`
+    fn set(&mut self, value: i64) -> Result<Outcome>;
+
+    /// Revert the most recent change and select the counter it applied to. Returns
+    /// false if there is nothing to undo, and fails with [`Error::Changed`] if the
+    /// counter has been changed by something else since.
+    fn undo(&mut self) -> Result<bool>;
+
+    /// Reapply the most recently undone change and select the counter it applied to.
+    /// Returns false if there is nothing to redo, and fails with [`Error::Changed`] if
+    /// the counter has been changed by something else since.
+    fn redo(&mut self) -> Result<bool>;
+
+    /// The counter that changes apply to
+    fn active(&self) -> &Counter {
+        &self.counters()[self.active_index()]
+    }
+
+    /// Select the next counter, wrapping around after the last
+    fn select_next(&mut self) {
+        let len = self.counters().len();
+        self.select((self.active_index() + 1) % len);
+    }
+
+    /// Select the previous counter, wrapping around before the first
+    fn select_previous(&mut self) {
+        let len = self.counters().len();
+        self.select((self.active_index() + len - 1) % len);
+    }
+}
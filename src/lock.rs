@@ -0,0 +1,66 @@
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io;
+use std::path::Path;
+
+/// How a counter file is protected against concurrent use by several processes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LockMode {
+    /// Hold an exclusive lock for the whole session; a second instance refuses to start
+    #[default]
+    Exclusive,
+    /// Lock only around each operation, re-reading the stored value before applying it,
+    /// so that several instances can tally into the same file
+    Shared,
+    /// No locking
+    None,
+}
+
+/// Advisory lock on a `<path>.lock` file next to the counter file
+///
+/// A separate file is used because the counter file itself is replaced on every write.
+/// The lock is released when the `FileLock` is dropped, if not before.
+pub struct FileLock {
+    file: File,
+}
+
+/// Releases the lock when dropped
+pub struct LockGuard<'a> {
+    file: &'a File,
+}
+
+impl FileLock {
+    pub fn open(counter_path: &Path) -> Result<Self, io::Error> {
+        let mut name = OsString::from(counter_path.file_name().unwrap_or_default());
+        name.push(".lock");
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(counter_path.with_file_name(name))?;
+        Ok(Self { file })
+    }
+
+    /// Take the lock, waiting for any other holder to release it
+    pub fn lock(&self) -> Result<LockGuard<'_>, io::Error> {
+        self.file.lock()?;
+        Ok(LockGuard { file: &self.file })
+    }
+
+    /// Take the lock until this `FileLock` is dropped. Returns false if it is held
+    /// elsewhere.
+    pub fn try_lock(&self) -> Result<bool, io::Error> {
+        match self.file.try_lock() {
+            Ok(()) => Ok(true),
+            Err(TryLockError::WouldBlock) => Ok(false),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+}
+
+impl Drop for LockGuard<'_> {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
@@ -1,53 +1,475 @@
+mod config;
+mod control;
+mod rate;
+mod screen;
+mod session;
+mod stats_output;
+mod web;
+
 use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufRead, BufReader, ErrorKind, Seek, Write};
-use std::path::PathBuf;
+use std::io::{self, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
 
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use crossterm::event::KeyEvent;
 use crossterm::{
-    ExecutableCommand, cursor,
-    event::{self, Event, KeyCode, KeyModifiers},
+    ExecutableCommand,
+    event::{self, Event, KeyCode, KeyEventState, KeyModifiers},
+    style::Stylize,
     terminal::{self, ClearType},
 };
 
+use counter::backup;
+use counter::stats::{self, Period, Stats};
+use counter::toml_lite::Value;
+use counter::{
+    Bounds, Counter, CounterStore, Error, FileCounter, Hooks, LockMode, Options, Outcome, Policy,
+    Result,
+};
+
+use crate::config::Config;
+use crate::control::{Request, Server};
+use crate::rate::RateTracker;
+use crate::session::TerminalSession;
+
+// How often to check for termination signals while waiting for a key press
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// How often to check for changes made to the file by others, and to redraw the --rate
+// status, while no key is pressed
+const REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+// How long hook commands may run unless configured otherwise
+const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Tally counter with file-backed storage
 #[derive(Parser)]
 #[command(version, about, long_about = None, max_term_width = 110)]
+#[allow(clippy::struct_excessive_bools)] // independent command line flags
 struct Args {
     /// Path to file where we will store the counter value (will be overwritten)
     #[arg()]
     path: PathBuf,
 
-    /// Starting value (default: 0)
+    /// Starting value of the selected counter (default: 0)
     #[arg()]
     start_value: Option<i64>,
 
+    /// Operate on the counter without the interactive display, print the resulting
+    /// value and exit
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Name of a counter to show, created if missing (may be repeated; the first one is
+    /// selected initially)
+    #[arg(short, long = "counter", value_name = "NAME", value_parser = parse_name)]
+    counters: Vec<String>,
+
     /// Disable syncing of data to disk on every operation
     #[arg(short, long)]
     no_sync: bool,
+
+    /// Record every change in a timestamped journal (<PATH>.log) and restore the count
+    /// by replaying it on startup
+    #[arg(short, long)]
+    journal: bool,
+
+    /// Replace a file that doesn't hold counters without asking (its contents are lost
+    /// unless --backup is also given)
+    #[arg(long)]
+    force: bool,
+
+    /// Copy a file that doesn't hold counters to <PATH>.<TIME>.bak, then replace it
+    /// without asking
+    #[arg(long)]
+    backup: bool,
+
+    /// Back up the file to <PATH>.<TIME>.auto.bak before every N changes
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    backup_every: Option<u32>,
+
+    /// Back up the file before a change if the last backup is more than MINUTES old
+    #[arg(long, value_name = "MINUTES", value_parser = clap::value_parser!(u64).range(1..))]
+    backup_interval: Option<u64>,
+
+    /// Number of backups made by --backup-every and --backup-interval to keep
+    #[arg(long, value_name = "K", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    backup_keep: u64,
+
+    /// How to guard the file against other counter instances
+    #[arg(short, long, value_enum, default_value_t)]
+    lock: LockModeArg,
+
+    /// Number of changes that can be undone (0 disables the undo history kept in
+    /// <PATH>.history)
+    #[arg(long, value_name = "DEPTH", default_value_t = 100)]
+    history: usize,
+
+    /// Lowest value a counter may take
+    #[arg(long, allow_negative_numbers = true)]
+    min: Option<i64>,
+
+    /// Highest value a counter may take
+    #[arg(long, allow_negative_numbers = true)]
+    max: Option<i64>,
+
+    /// What to do when a change would go past --min or --max
+    #[arg(long, value_enum, default_value_t)]
+    bound_policy: PolicyArg,
+
+    /// Show the count in large digits filling the terminal
+    #[arg(short, long)]
+    fullscreen: bool,
+
+    /// Config file with key bindings [default: ~/.config/counter/config.toml]
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Bind a key to an action, replacing the action's default keys (may be repeated,
+    /// e.g. --bind increment=kp-+ --bind increment=f13)
+    #[arg(long, value_name = "ACTION=KEY")]
+    bind: Vec<String>,
+
+    /// How to report errors on stderr
+    #[arg(long, value_enum, default_value_t)]
+    error_format: ErrorFormat,
+
+    /// Amount added or subtracted by each key press (and by `inc`/`dec` without N), saved
+    /// with the selected counter [default: the counter's saved step, or 1]
+    #[arg(short, long, value_parser = clap::value_parser!(i64).range(1..))]
+    step: Option<i64>,
+
+    /// Amount added or subtracted by the big step keys (']' and '[', or page up and down)
+    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(i64).range(1..))]
+    big_step: i64,
+
+    /// Value the selected counter is counted toward, saved in the file (shown as
+    /// progress, and as an ETA with --rate)
+    #[arg(long, allow_negative_numbers = true)]
+    target: Option<i64>,
+
+    /// What the selected counter counts, e.g. laps, saved in the file and shown after the
+    /// count
+    #[arg(long, value_name = "NAME")]
+    units: Option<String>,
+
+    /// Refuse changes that would take a counter past its target (or stop at the target
+    /// with --bound-policy saturate)
+    #[arg(long)]
+    stop_at_target: bool,
+
+    /// Shell command to run when a counter reaches its target or a countdown reaches zero
+    /// (overrides on-target in the config file's [hooks])
+    #[arg(long, value_name = "COMMAND")]
+    on_target: Option<String>,
+
+    /// Shell command to run after every change, with `COUNTER_NAME`, `COUNTER_OLD`,
+    /// `COUNTER_NEW`, `COUNTER_DELTA` and `COUNTER_PATH` set (overrides on-change in the
+    /// config file's [hooks])
+    #[arg(long, value_name = "COMMAND")]
+    on_change: Option<String>,
+
+    /// Seconds a hook command may run before it is killed [default: 10]
+    #[arg(long, value_name = "SECONDS")]
+    hook_timeout: Option<u64>,
+
+    /// Count down from N toward zero, saved in the file so that a restart resumes it: the
+    /// increment keys take one off and the display shows what remains
+    #[arg(long, value_name = "N", conflicts_with_all = ["start_value", "target"])]
+    countdown: Option<i64>,
+
+    /// Quit once the countdown reaches zero; otherwise exit with status 8
+    #[arg(long)]
+    exit_on_complete: bool,
+
+    /// Show the change and elapsed time this session, the rate, and the time left to reach
+    /// --target
+    #[arg(short, long)]
+    rate: bool,
+
+    /// Accept commands (get, inc [N], dec [N], set N, subscribe) from other programs on a
+    /// Unix socket at PATH while running interactively
+    #[arg(long, value_name = "PATH")]
+    socket: Option<PathBuf>,
+
+    /// Write the counters in the Prometheus text format to FILE whenever they change (for
+    /// node-exporter's textfile collector), keeping running totals of increments and
+    /// decrements in the counter file
+    #[arg(long, value_name = "FILE")]
+    metrics_file: Option<PathBuf>,
+
+    /// Minutes over which --rate is measured
+    #[arg(long, value_name = "MINUTES", default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    rate_window: u64,
 }
 
-struct FileCounter {
-    file: File,
-    count: i64,
-    data_sync: bool,
+#[derive(Subcommand)]
+enum Command {
+    /// Print the value of the selected counter
+    Get,
+    /// Increase the selected counter
+    Inc {
+        /// Amount to add (default: --step)
+        n: Option<i64>,
+    },
+    /// Decrease the selected counter
+    Dec {
+        /// Amount to subtract (default: --step)
+        n: Option<i64>,
+    },
+    /// Set the selected counter to a value
+    Set {
+        #[arg(allow_negative_numbers = true)]
+        value: i64,
+    },
+    /// Set the selected counter to 0
+    Reset,
+    /// Show the counters, following changes made to the file by other programs, without
+    /// ever writing to it
+    Watch,
+    /// Serve the counters over HTTP: a JSON API, live updates, Prometheus metrics and a
+    /// dashboard page
+    Serve {
+        /// Address to listen on
+        #[arg(long, value_name = "ADDRESS", default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// Compact the journal into a single snapshot of the current values
+    Compact,
+    /// List the backups of the file with their values, or restore one (after backing up
+    /// the current contents)
+    Restore {
+        /// Number of the backup to restore, as listed
+        backup: Option<usize>,
+    },
+    /// Report changes to the selected counter per period, the busiest periods, the
+    /// average rate and sessions, from the journal
+    Stats {
+        /// Length of the periods to total changes over (UTC)
+        #[arg(long, value_enum, default_value_t)]
+        period: PeriodArg,
+        /// Minutes without changes that end a session
+        #[arg(long, value_name = "MINUTES", default_value_t = 30)]
+        session_gap: u64,
+        /// Number of busiest periods to list
+        #[arg(long, value_name = "N", default_value_t = 3)]
+        top: usize,
+        #[arg(long, value_enum, default_value_t)]
+        format: stats_output::Format,
+    },
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum ErrorFormat {
+    /// A human-readable message
+    #[default]
+    Text,
+    /// A JSON object with `error` (a stable code), `message` and `exit_code` fields
+    Json,
+}
+
+// Command line counterparts of the library's option types, which don't depend on clap
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum LockModeArg {
+    /// Hold an exclusive lock for the whole session; a second instance refuses to start
+    #[default]
+    Exclusive,
+    /// Lock only around each operation, re-reading the stored value before applying it,
+    /// so that several instances can tally into the same file
+    Shared,
+    /// No locking
+    None,
+}
+
+impl From<LockModeArg> for LockMode {
+    fn from(mode: LockModeArg) -> Self {
+        match mode {
+            LockModeArg::Exclusive => Self::Exclusive,
+            LockModeArg::Shared => Self::Shared,
+            LockModeArg::None => Self::None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum PolicyArg {
+    /// Stop at the bound
+    Saturate,
+    /// Continue from the opposite bound, like a mechanical counter
+    Wrap,
+    /// Leave the count unchanged
+    #[default]
+    Reject,
+}
+
+impl From<PolicyArg> for Policy {
+    fn from(policy: PolicyArg) -> Self {
+        match policy {
+            PolicyArg::Saturate => Self::Saturate,
+            PolicyArg::Wrap => Self::Wrap,
+            PolicyArg::Reject => Self::Reject,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum PeriodArg {
+    Hour,
+    #[default]
+    Day,
+    Week,
+}
+
+impl From<PeriodArg> for Period {
+    fn from(period: PeriodArg) -> Self {
+        match period {
+            PeriodArg::Hour => Self::Hour,
+            PeriodArg::Day => Self::Day,
+            PeriodArg::Week => Self::Week,
+        }
+    }
+}
+
+fn parse_name(name: &str) -> Result<String, String> {
+    if counter::is_valid_name(name) {
+        Ok(name.to_string())
+    } else {
+        Err("counter names may only contain letters, digits, '-' and '_'".to_string())
+    }
+}
+
+/// Actions that can be bound to keys in the interactive loop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    Increment,
+    Decrement,
+    BigIncrement,
+    BigDecrement,
+    // Part of a numeric prefix that repeats the next increment or decrement
+    Digit(u8),
+    Cancel,
+    Next,
+    Previous,
+    Select(usize),
+    Undo,
+    Redo,
+    Quit,
 }
 
 fn get_character_choice<'a, T>(
     prompt: &str,
     choice_map: &'a HashMap<KeyEvent, T>,
-) -> io::Result<&'a T> {
+) -> Result<&'a T> {
     loop {
-        // Clear line and show prompt
-        io::stdout().execute(terminal::Clear(ClearType::CurrentLine))?;
-        print!("\r{prompt}");
-        io::stdout().flush()?;
+        if let Some(choice) = read_choice(|| draw_line(prompt), None, choice_map)? {
+            return Ok(choice);
+        }
+    }
+}
 
-        // Read key event, return map value on match
-        if let Event::Key(key_event) = event::read()? {
+// Percentage and a bar showing `progress` (a fraction) toward a target
+fn progress_bar(progress: f64) -> String {
+    const WIDTH: u8 = 10;
+    let clamped = progress.clamp(0.0, 1.0);
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // within 0..=WIDTH
+    let filled = (clamped * f64::from(WIDTH)).round() as usize;
+    format!(
+        "{:.0}% [{}{}]",
+        progress * 100.0,
+        "█".repeat(filled),
+        "░".repeat(usize::from(WIDTH) - filled)
+    )
+}
+
+// Undo or redo the last change, returning the status line message, and ringing the
+// bell if it was refused
+fn history_status(counter: &mut FileCounter, undo: bool) -> Result<String> {
+    let (done, what) = if undo {
+        (counter.undo(), "undo")
+    } else {
+        (counter.redo(), "redo")
+    };
+    Ok(match done {
+        Ok(true) => String::new(),
+        Ok(false) => format!("nothing to {what}"),
+        Err(Error::Changed { .. }) => {
+            print!("\x07");
+            format!("can't {what}: changed by something else")
+        }
+        Err(e) => return Err(e),
+    })
+}
+
+// Status line message for the `outcome` of a change, ringing the bell if it was refused
+fn outcome_status(counter: &FileCounter, outcome: Result<Outcome>) -> Result<String> {
+    let bounds = counter.bounds();
+    let target = counter.active().target;
+    Ok(match outcome {
+        Ok(Outcome::Saturated(v)) if Some(v) == target => "at target".to_string(),
+        Ok(Outcome::Saturated(v)) if v == bounds.max => "at maximum".to_string(),
+        Ok(Outcome::Saturated(_)) => "at minimum".to_string(),
+        Ok(Outcome::Wrapped(_)) => "wrapped".to_string(),
+        Ok(Outcome::Applied(_)) => String::new(),
+        Err(Error::OutOfRange { bounds, .. }) => {
+            print!("\x07");
+            format!("out of range ({}..{})", bounds.min, bounds.max)
+        }
+        Err(Error::Overflow { .. }) => {
+            print!("\x07");
+            "overflow".to_string()
+        }
+        Err(e) => return Err(e),
+    })
+}
+
+// Whether the active counter has just reached its target
+fn reached_target(counter: &FileCounter, before: &Counter) -> bool {
+    let active = counter.active();
+    active.name == before.name && active.at_target() && !before.at_target()
+}
+
+// Show `text` in green once `counter` has reached its target
+fn highlight_at_target(counter: &Counter, text: String) -> String {
+    if counter.at_target() {
+        text.green().to_string()
+    } else {
+        text
+    }
+}
+
+// Clear line and show prompt
+fn draw_line(prompt: &str) -> Result<()> {
+    io::stdout().execute(terminal::Clear(ClearType::CurrentLine))?;
+    print!("\r{prompt}");
+    Ok(io::stdout().flush()?)
+}
+
+// Call `draw` and wait for a key press, until one is found in `choice_map`. Other
+// events, such as the terminal being resized, just cause a redraw. Returns None if
+// `refresh` passes without any.
+fn read_choice<T>(
+    mut draw: impl FnMut() -> Result<()>,
+    refresh: Option<Duration>,
+    choice_map: &HashMap<KeyEvent, T>,
+) -> Result<Option<&T>> {
+    loop {
+        draw()?;
+        let drawn = Instant::now();
+        while !event::poll(SIGNAL_POLL_INTERVAL)? {
+            session::check_signal()?;
+            if refresh.is_some_and(|interval| drawn.elapsed() >= interval) {
+                return Ok(None);
+            }
+        }
+
+        // Read key event, return map value on match. Of the lock key states only the
+        // keypad flag matters for bindings.
+        if let Event::Key(mut key_event) = event::read()? {
+            key_event.state &= KeyEventState::KEYPAD;
             if let Some(val) = choice_map.get(&key_event) {
-                return Ok(val);
+                return Ok(Some(val));
             }
         }
     }
@@ -63,169 +485,746 @@ const fn key(c: char) -> KeyEvent {
     keycode(KeyCode::Char(c))
 }
 
-fn user_ok_with_overwrite() -> io::Result<bool> {
-    let prompt = "File contains non-counter data. Use anyway? (data will be lost!)  [y/n]";
+// What to do about a file that doesn't hold counters
+enum Resolution {
+    // Replace it, after copying it to a backup if set
+    Replace { backup: bool },
+    // Use another file instead
+    UseOther(PathBuf),
+}
+
+// Ask the user what to do about `path`, which doesn't hold counters, after showing the
+// start of its contents. None if it is to be left alone.
+fn ask_about_invalid_file(path: &Path) -> Result<Option<Resolution>> {
+    #[derive(Clone, Copy)]
+    enum Choice {
+        Backup,
+        Overwrite,
+        OtherPath,
+        No,
+        Quit,
+    }
+
+    println!("{} contains non-counter data:", path.display());
+    for line in preview(path)? {
+        println!("  | {line}");
+    }
+    let prompt = "[b]ack up and replace it, [o]verwrite it (data will be lost!), use another \
+                  [p]ath, or [n]o?";
 
     // Map of input key presses to value we want returned from get_character_choice()
     let choice_map = HashMap::from([
-        // Yes
-        (key('y'), 'y'),
-        (key('Y'), 'y'),
-        // No
-        (key('n'), 'n'),
-        (key('N'), 'n'),
-        // Quit
-        (key('q'), 'q'),
-        (key('Q'), 'q'),
+        (key('b'), Choice::Backup),
+        (key('B'), Choice::Backup),
+        (key('o'), Choice::Overwrite),
+        (key('O'), Choice::Overwrite),
+        (key('p'), Choice::OtherPath),
+        (key('P'), Choice::OtherPath),
+        (key('n'), Choice::No),
+        (key('N'), Choice::No),
+        (key('q'), Choice::Quit),
+        (key('Q'), Choice::Quit),
         (
             KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL), // ctrl-c
-            'q',
+            Choice::Quit,
         ),
     ]);
 
-    terminal::enable_raw_mode()?;
-    io::stdout().execute(cursor::Hide)?;
-    let choice = get_character_choice(prompt, &choice_map)?;
-    io::stdout().execute(cursor::Show)?;
-    terminal::disable_raw_mode()?;
-    println!();
-    match choice {
-        'y' => Ok(true),
-        'n' => Ok(false),
-        'q' => std::process::exit(1),
-        c => panic!("internal error: unexpected character accepted: '{c}'"),
-    }
-}
-
-// A counter that persists the count value to a text file
-impl FileCounter {
-    fn new(path: PathBuf, value: Option<i64>, data_sync: bool) -> Result<Self, io::Error> {
-        // Initial count precedence:
-        //   1) `value` argument
-        //   2) first line of file given by `path` argument
-        //   3) 0
-
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .truncate(false)
-            .open(path)?;
-
-        let mut count: i64 = 0;
-
-        if let Some(value) = value {
-            count = value;
-        } else {
-            let mut reader = BufReader::new(&file);
-            let mut line = String::new();
-            if reader.read_line(&mut line).is_ok() {
-                if let Ok(value) = line.trim_end().parse::<i64>() {
-                    count = value;
-                } else if !line.is_empty() && !user_ok_with_overwrite()? {
-                    return Err(io::Error::new(
-                        ErrorKind::InvalidData,
-                        "File contained non-counter data",
-                    ));
+    let resolution = {
+        let _session = TerminalSession::start(false, false)?;
+        loop {
+            match *get_character_choice(prompt, &choice_map)? {
+                Choice::Backup => break Some(Resolution::Replace { backup: true }),
+                Choice::Overwrite => break Some(Resolution::Replace { backup: false }),
+                Choice::OtherPath => {
+                    // Back to the choices if no path is given
+                    if let Some(other) = read_text("Path: ")?.filter(|p| !p.is_empty()) {
+                        break Some(Resolution::UseOther(PathBuf::from(other)));
+                    }
                 }
+                Choice::No => break None,
+                Choice::Quit => return Err(Error::Aborted),
             }
         }
+    };
+    println!();
+    Ok(resolution)
+}
+
+// The first few lines of the file at `path`, shortened to fit on a line, with anything
+// that isn't printable text replaced
+fn preview(path: &Path) -> Result<Vec<String>> {
+    const LINES: usize = 5;
+    const WIDTH: usize = 60;
 
-        let mut counter = Self {
-            file,
-            count,
-            data_sync,
+    let contents = std::fs::read(path)?;
+    let text = String::from_utf8_lossy(&contents);
+    let mut lines: Vec<String> = text
+        .lines()
+        .take(LINES)
+        .map(|line| {
+            let mut line: String = line
+                .chars()
+                .map(|c| if c.is_control() { '?' } else { c })
+                .collect();
+            if line.chars().count() > WIDTH {
+                line = line.chars().take(WIDTH).chain(['…']).collect();
+            }
+            line
+        })
+        .collect();
+    if text.lines().count() > LINES {
+        lines.push("…".to_string());
+    }
+    Ok(lines)
+}
+
+// Read a line of text typed after `prompt`. None if cancelled with Esc or Ctrl-C.
+fn read_text(prompt: &str) -> Result<Option<String>> {
+    let mut text = String::new();
+    loop {
+        draw_line(&format!("{prompt}{text}"))?;
+        while !event::poll(SIGNAL_POLL_INTERVAL)? {
+            session::check_signal()?;
+        }
+        let Event::Key(key_event) = event::read()? else {
+            continue;
         };
-        counter.persist()?;
-        Ok(counter)
+        match (key_event.code, key_event.modifiers) {
+            (KeyCode::Enter, _) => return Ok(Some(text)),
+            (KeyCode::Esc, _) | (KeyCode::Char('c'), KeyModifiers::CONTROL) => return Ok(None),
+            (KeyCode::Backspace, _) => _ = text.pop(),
+            (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => text.push(c),
+            _ => {}
+        }
     }
+}
 
-    fn increment(&mut self) -> Result<(), io::Error> {
-        match self.count.checked_add(1) {
-            None => {
-                terminal::disable_raw_mode()?;
-                println!("\noverflow!");
-                terminal::enable_raw_mode()?;
+// The interactive prompt: the count alone, or every counter with the active one
+// highlighted, followed by a status message and any numeric prefix typed so far
+fn prompt(
+    counter: &impl CounterStore,
+    status: &str,
+    repeat: Option<i64>,
+    watching: bool,
+) -> String {
+    let status = if status.is_empty() {
+        String::new()
+    } else {
+        format!("  {}", status.bold())
+    };
+    let repeat = repeat.map(|n| format!("  {n}")).unwrap_or_default();
+    let units = |c: &Counter| {
+        c.units
+            .as_ref()
+            .map(|u| format!(" {u}"))
+            .unwrap_or_default()
+    };
+    if let [only] = counter.counters() {
+        let (label, of) = match (only.total, only.target) {
+            (Some(total), _) => ("Remaining", format!(" of {total}")),
+            (None, Some(target)) => ("Count", format!(" / {target}")),
+            (None, None) => ("Count", String::new()),
+        };
+        let progress = only
+            .progress()
+            .map(|progress| format!("  {}", progress_bar(progress)));
+        return format!(
+            "{label}: {}{of}{}{}    [{}q]{status}{repeat}",
+            highlight_at_target(only, only.count.to_string()),
+            units(only),
+            progress.unwrap_or_default(),
+            if watching { "" } else { "+/-/" }
+        );
+    }
+
+    let counts = counter
+        .counters()
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let count = match (c.total, c.target) {
+                (Some(_), _) => format!("{} left", c.count),
+                (None, Some(target)) => format!("{}/{target}", c.count),
+                (None, None) => c.count.to_string(),
+            };
+            let count = highlight_at_target(c, count);
+            let text = format!(" {}: {count}{} ", c.name, units(c));
+            if i == counter.active_index() {
+                text.reverse().to_string()
+            } else {
+                text
             }
-            Some(val) => self.count = val,
-        }
+        })
+        .collect::<Vec<_>>()
+        .join("|");
+    let keys = if watching { "tab/q" } else { "+/-/tab/q" };
+    format!("{counts}    [{keys}]{status}{repeat}")
+}
 
-        self.persist()?;
-        Ok(())
+// Exit with a usage error for combinations of arguments that clap can't check
+fn check_args(args: &Args) {
+    if args.start_value.is_some() && args.command.is_some() {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "a starting value can't be combined with a subcommand (use `set`)",
+            )
+            .exit();
+    }
+    if let Some(command @ (Command::Compact | Command::Stats { .. })) = &args.command
+        && !args.journal
+    {
+        let name = match command {
+            Command::Compact => "compact",
+            _ => "stats",
+        };
+        Args::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                format!("`{name}` requires --journal"),
+            )
+            .exit();
+    }
+}
+
+// Hooks from the config file, overridden by the command line
+fn hooks(config: &Config, args: &Args) -> Hooks {
+    let mut hooks = config.hooks.clone();
+    if args.on_change.is_some() {
+        hooks.on_change.clone_from(&args.on_change);
+    }
+    if args.on_target.is_some() {
+        hooks.on_target.clone_from(&args.on_target);
     }
+    if let Some(seconds) = args.hook_timeout {
+        hooks.timeout = Some(Duration::from_secs(seconds));
+    }
+    hooks.timeout.get_or_insert(DEFAULT_HOOK_TIMEOUT);
+    hooks
+}
 
-    fn decrement(&mut self) -> Result<(), io::Error> {
-        match self.count.checked_sub(1) {
-            None => {
-                terminal::disable_raw_mode()?;
-                println!("\nunderflow!");
-                terminal::enable_raw_mode()?;
+// Open the counter file, dealing with a file that doesn't hold counters as the arguments
+// say, or as the user chooses when running interactively
+fn open(args: &Args, options: Options) -> Result<FileCounter> {
+    let open = |path: &Path, options: &Options| {
+        FileCounter::new(
+            path.to_path_buf(),
+            args.start_value,
+            &args.counters,
+            options,
+        )
+    };
+    let mut path = args.path.clone();
+    loop {
+        let resolution = match open(&path, &options) {
+            // Replace a file that doesn't hold counters if told to, or if the user agrees
+            // when running interactively
+            Err(Error::Corrupt(_)) if args.force || args.backup => Resolution::Replace {
+                backup: args.backup,
+            },
+            Err(Error::Corrupt(_)) if args.command.is_none() => {
+                match ask_about_invalid_file(&path)? {
+                    Some(resolution) => resolution,
+                    None => return Err(Error::Corrupt(path)),
+                }
+            }
+            result => return result,
+        };
+        match resolution {
+            Resolution::Replace { backup } => {
+                if backup {
+                    // Next to the file a link points to, like the automatic backups
+                    let target = std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+                    let backup = backup::create(&target)?;
+                    eprintln!(
+                        "Saved the old contents of {} to {}",
+                        target.display(),
+                        backup.display()
+                    );
+                }
+                let options = Options {
+                    overwrite_invalid: true,
+                    ..options
+                };
+                return open(&path, &options);
             }
-            Some(val) => self.count = val,
+            Resolution::UseOther(other) => path = other,
         }
+    }
+}
+
+fn main_real(args: &Args) -> Result<()> {
+    check_args(args);
+    let config = match config::load(args.config.as_deref(), &args.bind) {
+        Ok(config) => config,
+        Err(e) => {
+            report(args.error_format, "config", &e, 2);
+            std::process::exit(2);
+        }
+    };
 
-        self.persist()?;
-        Ok(())
+    let bounds = Bounds {
+        min: args.min.unwrap_or(i64::MIN),
+        max: args.max.unwrap_or(i64::MAX),
+        policy: args.bound_policy.into(),
+    };
+    if bounds.min > bounds.max {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "--min must not be greater than --max",
+            )
+            .exit();
     }
 
-    fn persist(&mut self) -> Result<(), io::Error> {
-        self.file.seek(io::SeekFrom::Start(0))?;
-        self.file.set_len(0)?;
-        self.file.write_all(self.count.to_string().as_bytes())?;
-        self.file.flush()?;
-        if self.data_sync {
-            self.file.sync_data()?;
+    let options = Options {
+        data_sync: !args.no_sync,
+        journal: args.journal,
+        lock_mode: args.lock.into(),
+        bounds,
+        history_depth: args.history,
+        read_only: matches!(
+            args.command,
+            Some(Command::Get | Command::Watch | Command::Stats { .. })
+        ),
+        overwrite_invalid: false,
+        target: args.target,
+        stop_at_target: args.stop_at_target,
+        countdown: args.countdown,
+        hooks: hooks(&config, args),
+        totals: args.metrics_file.is_some() || matches!(args.command, Some(Command::Serve { .. })),
+        metrics_file: args.metrics_file.clone(),
+        step: args.step,
+        units: args.units.clone(),
+        backup_every: args.backup_every,
+        backup_interval: args
+            .backup_interval
+            .map(|minutes| Duration::from_secs(minutes.saturating_mul(60))),
+        backup_keep: usize::try_from(args.backup_keep).unwrap_or(usize::MAX),
+    };
+    if let Some(Command::Restore { backup }) = args.command {
+        return restore(args, options, backup);
+    }
+    let mut counter = open(args, options)?;
+
+    let step = step(&counter, args);
+    match args.command {
+        None | Some(Command::Watch) => {
+            return interactive(&mut counter, &config, args);
+        }
+        Some(Command::Get) => {}
+        Some(Command::Inc { n }) => _ = counter.increment(n.unwrap_or(step))?,
+        Some(Command::Dec { n }) => _ = counter.decrement(n.unwrap_or(step))?,
+        Some(Command::Set { value }) => _ = counter.set(value)?,
+        Some(Command::Reset) => _ = counter.set(0)?,
+        Some(Command::Compact) => counter.compact()?,
+        Some(Command::Restore { .. }) => unreachable!("handled above"),
+        Some(Command::Serve { ref listen }) => return Ok(web::serve(counter, listen, args.step)?),
+        Some(Command::Stats {
+            period,
+            session_gap,
+            top,
+            format,
+        }) => {
+            let period = period.into();
+            let name = &counter.active().name;
+            let stats = Stats::new(
+                &counter.journal_entries()?,
+                name,
+                period,
+                session_gap.saturating_mul(60),
+            );
+            let report = stats_output::Report {
+                name,
+                period,
+                session_gap,
+                top,
+            };
+            print!("{}", report.render(&stats, format));
+            return Ok(());
         }
-        Ok(())
     }
+    println!("{}", counter.active().count);
+    // The hooks' timeout is enforced by threads that end with the program
+    counter.hooks().finish();
+    check_complete(&counter, args)
 }
 
-fn main_real() -> Result<(), io::Error> {
-    let args = Args::parse();
-    let mut counter = FileCounter::new(args.path, args.start_value, !args.no_sync)?;
+// List the backups of the counter file, or restore the one numbered `backup` in the list
+fn restore(args: &Args, options: Options, backup: Option<usize>) -> Result<()> {
+    // Backups are kept next to the file a link points to
+    let path = std::fs::canonicalize(&args.path).unwrap_or_else(|_| args.path.clone());
+    let backups = backup::list(&path)?;
+    let Some(number) = backup else {
+        if backups.is_empty() {
+            println!("No backups of {}", path.display());
+        }
+        for (number, backup) in backups.iter().enumerate() {
+            let values = backup.counters()?.map_or_else(
+                || "(not counters)".to_string(),
+                |counters| {
+                    let values: Vec<String> = counters
+                        .iter()
+                        .map(|c| format!("{}={}", c.name, c.count))
+                        .collect();
+                    values.join(" ")
+                },
+            );
+            let kind = if backup.automatic { "auto  " } else { "manual" };
+            println!(
+                "{:>3}  {}  {kind}  {values}",
+                number + 1,
+                stats::format_time(backup.time)
+            );
+        }
+        return Ok(());
+    };
+
+    let Some(backup) = number.checked_sub(1).and_then(|i| backups.get(i)) else {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::InvalidValue,
+                format!("there is no backup {number} (see `restore` without a number)"),
+            )
+            .exit();
+    };
+    let Some(counters) = backup.counters()? else {
+        return Err(Error::Corrupt(backup.path.clone()));
+    };
+    // The current contents are kept, so that the restore can be undone
+    if path.exists() {
+        let saved = backup::create(&path)?;
+        eprintln!(
+            "Saved the current contents of {} to {}",
+            path.display(),
+            saved.display()
+        );
+    }
+    let options = Options {
+        overwrite_invalid: true,
+        ..options
+    };
+    let mut counter = FileCounter::new(path, None, &args.counters, &options)?;
+    counter.restore(counters)?;
+    println!("{}", counter.active().count);
+    counter.hooks().finish();
+    Ok(())
+}
+
+fn interactive(counter: &mut FileCounter, config: &Config, args: &Args) -> Result<()> {
+    // Keypad keys can only be told apart with the keyboard enhancement protocol. A
+    // terminal that doesn't answer the query is taken not to support it.
+    let enhanced =
+        config.uses_keypad() && terminal::supports_keyboard_enhancement().unwrap_or(false);
+
+    let server = args.socket.as_deref().map(Server::start).transpose()?;
+    let session = TerminalSession::start(args.fullscreen, enhanced)?;
+    let result = event_loop(counter, config, args, server.as_ref());
+    // Every change is saved as it is made, but save again in case the last attempt
+    // failed. Not in shared lock mode, where that could undo other instances' changes,
+    // nor over changes made by something else since.
+    let persisted = match counter.lock_mode() {
+        LockMode::Shared => Ok(()),
+        _ => counter.refresh().and_then(|_| counter.persist()),
+    };
+    drop(session);
+
+    if args.fullscreen {
+        print!("{}", prompt(counter, "", None, watching(args)));
+    }
+    println!();
+    counter.hooks().finish();
+    hooks(config, args).exit(counter.path(), counter.active());
+    result
+        .and(persisted)
+        .and_then(|()| check_complete(counter, args))
+}
+
+// With --exit-on-complete, fail unless the selected countdown has reached zero
+fn check_complete(counter: &FileCounter, args: &Args) -> Result<()> {
+    let active = counter.active();
+    if args.exit_on_complete && active.is_countdown() && !active.at_target() {
+        return Err(Error::Incomplete(active.count));
+    }
+    Ok(())
+}
+
+// Whether the counters are only being displayed, by the watch subcommand
+const fn watching(args: &Args) -> bool {
+    matches!(args.command, Some(Command::Watch))
+}
+
+// Heading above the big digits: the active counter's name when there are several, and
+// its units
+fn title(active: &Counter, counters: usize) -> String {
+    let title = match (counters, active.is_countdown()) {
+        (1, false) => String::new(),
+        (1, true) => "remaining".to_string(),
+        (_, false) => active.name.clone(),
+        (_, true) => format!("{} remaining", active.name),
+    };
+    match &active.units {
+        Some(units) if title.is_empty() => units.clone(),
+        Some(units) => format!("{title} {units}"),
+        None => title,
+    }
+}
+
+// Amount a key press (or `inc`/`dec` without an amount) changes the active counter by
+fn step(counter: &FileCounter, args: &Args) -> i64 {
+    args.step.or_else(|| counter.active().step).unwrap_or(1)
+}
+
+// Apply a step key `action` `times` over. A countdown counts the items done off what
+// remains, so its increment keys decrease the count.
+fn change(counter: &mut FileCounter, action: &Action, times: i64, args: &Args) -> Result<Outcome> {
+    let step = match action {
+        Action::BigIncrement | Action::BigDecrement => args.big_step,
+        _ => step(counter, args),
+    };
+    let up = matches!(action, Action::Increment | Action::BigIncrement);
+    if up == counter.active().is_countdown() {
+        counter.decrement(times.saturating_mul(step))
+    } else {
+        counter.increment(times.saturating_mul(step))
+    }
+}
+
+// Ring the bell and say so in `status` if the active counter has just reached its target.
+// Returns whether the session is over: a countdown has completed with --exit-on-complete.
+fn announce_target(
+    counter: &FileCounter,
+    before: &Counter,
+    args: &Args,
+    status: &mut String,
+) -> Result<bool> {
+    if !reached_target(counter, before) {
+        return Ok(false);
+    }
+    print!("\x07");
+    if !counter.active().is_countdown() {
+        *status = "target reached".to_string();
+    } else if args.exit_on_complete {
+        if !args.fullscreen {
+            draw_line(&prompt(counter, "complete", None, false))?;
+        }
+        return Ok(true);
+    } else {
+        *status = "complete".to_string();
+    }
+    Ok(false)
+}
+
+// Apply a command from the control socket to the active counter and answer it
+fn serve(
+    counter: &mut FileCounter,
+    request: Request,
+    args: &Args,
+    subscribers: &mut Vec<Sender<String>>,
+) -> Result<()> {
+    let outcome = match request.command {
+        control::Command::Get => Ok(Outcome::Applied(counter.active().count)),
+        control::Command::Subscribe => {
+            _ = request.reply.send(counter.active().count.to_string());
+            subscribers.push(request.reply);
+            return Ok(());
+        }
+        _ if watching(args) => {
+            _ = request
+                .reply
+                .send("error read-only: only watching".to_string());
+            return Ok(());
+        }
+        control::Command::Inc(n) => counter.increment(n.unwrap_or_else(|| step(counter, args))),
+        control::Command::Dec(n) => counter.decrement(n.unwrap_or_else(|| step(counter, args))),
+        control::Command::Set(value) => counter.set(value),
+    };
+    let answer = match &outcome {
+        Ok(_) => counter.active().count.to_string(),
+        Err(e) => format!("error {}: {e}", e.code()),
+    };
+    _ = request.reply.send(answer);
+    // Like a key press, a change the bounds refuse doesn't end the session
+    match outcome {
+        Err(Error::OutOfRange { .. } | Error::Overflow { .. }) | Ok(_) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// Send the active counter's value to the control socket's subscribers when it has
+// changed since `published`, dropping those that have gone
+fn publish(
+    counter: &FileCounter,
+    subscribers: &mut Vec<Sender<String>>,
+    published: &mut Option<(String, i64)>,
+) {
+    let active = counter.active();
+    let current = Some((active.name.clone(), active.count));
+    if current != *published {
+        subscribers.retain(|subscriber| subscriber.send(active.count.to_string()).is_ok());
+        *published = current;
+    }
+}
 
+// Draw `prompt`, followed by the --rate status if enabled, either below the active count
+// in big digits or on its own
+fn draw(
+    counter: &FileCounter,
+    args: &Args,
+    mut prompt: String,
+    rate: Option<&mut RateTracker>,
+    help: &[String],
+) -> Result<()> {
+    let active = counter.active();
+    if let Some(rate) = rate {
+        let now = Instant::now();
+        rate.observe(now, &active.name, active.count);
+        let status = rate.status(now, &active.name, active.goal());
+        prompt = format!("{prompt}  {status}");
+    }
+    if args.fullscreen {
+        let title = title(active, counter.counters().len());
+        Ok(screen::draw(
+            &title,
+            active.count,
+            active.at_target(),
+            &prompt,
+            help,
+        )?)
+    } else {
+        draw_line(&prompt)
+    }
+}
+
+// Read and act on key presses until quit
+fn event_loop(
+    counter: &mut FileCounter,
+    config: &Config,
+    args: &Args,
+    server: Option<&Server>,
+) -> Result<()> {
     // Map of input key presses to value we want returned from get_character_choice()
-    let choice_map = HashMap::from([
-        // Increment keys
-        (key('+'), '+'),
-        (key('='), '+'), // '+' without shift
-        (key(' '), '+'),
-        // Decrement keys
-        (key('-'), '-'),
-        (key('_'), '-'), // '-' with shift
-        (keycode(KeyCode::Backspace), '-'),
-        // Quit keys
-        (key('q'), 'q'),
-        (key('Q'), 'q'),
-        (
-            KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL), // ctrl-c
-            'q',
-        ),
-    ]);
+    let choice_map = &config.keys;
+    let watching = watching(args);
+    let help = config.help(watching);
+    let mut rate = args
+        .rate
+        .then(|| RateTracker::new(Duration::from_secs(args.rate_window.saturating_mul(60))));
 
-    terminal::enable_raw_mode()?;
-    io::stdout().execute(cursor::Hide)?;
+    let mut repeat: Option<i64> = None;
+    let mut status = String::new();
+    let mut unchanged = false;
+    // Requests on the control socket are handled while waiting for a key press
+    let refresh = server.map_or(REFRESH_INTERVAL, |_| SIGNAL_POLL_INTERVAL);
+    let mut drawn = Instant::now();
+    let mut subscribers = Vec::new();
+    let mut published = None;
     loop {
-        let prompt = format!("Count: {}    [+/-/q]", counter.count);
-        let choice = get_character_choice(&prompt, &choice_map)?;
-        match choice {
-            '+' => counter.increment()?,
-            '-' => counter.decrement()?,
-            'q' => break,
-            c => panic!("internal error: unexpected character accepted: '{c}'"),
+        for request in server.iter().flat_map(|server| server.requests()) {
+            let before = counter.active().clone();
+            serve(counter, request, args, &mut subscribers)?;
+            if announce_target(counter, &before, args, &mut status)? {
+                return Ok(());
+            }
+            unchanged = false;
+        }
+        publish(counter, &mut subscribers, &mut published);
+
+        let draw = || {
+            // Leave the screen alone when there is nothing new to show
+            if mem::take(&mut unchanged) {
+                return Ok(());
+            }
+            drawn = Instant::now();
+            let prompt = prompt(counter, &status, repeat, watching);
+            draw(counter, args, prompt, rate.as_mut(), &help)
         };
-    }
-    io::stdout().execute(cursor::Show)?;
-    terminal::disable_raw_mode()?;
-    println!();
+        let Some(choice) = read_choice(draw, Some(refresh), choice_map)? else {
+            // Follow changes made by others. A file that can't be read is reported here,
+            // and stops the next change from overwriting it.
+            let refreshed = counter.refresh().unwrap_or_else(|e| {
+                status = e.to_string();
+                true
+            });
+            unchanged = !refreshed && (rate.is_none() || drawn.elapsed() < REFRESH_INTERVAL);
+            continue;
+        };
+        let before = counter.active().clone();
+        let times = repeat.unwrap_or(1);
+        status.clear();
+        let outcome = match choice {
+            Action::Increment
+            | Action::Decrement
+            | Action::BigIncrement
+            | Action::BigDecrement
+            | Action::Digit(_)
+            | Action::Undo
+            | Action::Redo
+                if watching =>
+            {
+                None
+            }
+            Action::Increment | Action::Decrement | Action::BigIncrement | Action::BigDecrement => {
+                Some(change(counter, choice, times, args))
+            }
+            Action::Digit(d) => {
+                // A leading zero is ignored rather than starting a count of 0
+                if repeat.is_some() || *d != 0 {
+                    let prefix = repeat.unwrap_or(0).saturating_mul(10);
+                    repeat = Some(prefix.saturating_add(i64::from(*d)));
+                }
+                None
+            }
+            Action::Cancel => None,
+            Action::Next => {
+                counter.select_next();
+                None
+            }
+            Action::Previous => {
+                counter.select_previous();
+                None
+            }
+            Action::Select(i) => {
+                counter.select(*i);
+                None
+            }
+            Action::Undo | Action::Redo => {
+                status = history_status(counter, matches!(choice, Action::Undo))?;
+                None
+            }
+            Action::Quit => break,
+        };
+        if !matches!(choice, Action::Digit(_)) {
+            repeat = None;
+        }
 
+        if let Some(outcome) = outcome {
+            status = outcome_status(counter, outcome)?;
+        }
+        if announce_target(counter, &before, args, &mut status)? {
+            break;
+        }
+    }
     Ok(())
 }
 
 fn main() {
-    // Show the Display of errors, not Debug
-    if let Err(e) = main_real() {
-        eprintln!("Error: {e}");
-        std::process::exit(1);
+    let args = Args::parse();
+    if let Err(e) = main_real(&args) {
+        report(args.error_format, e.code(), &e.to_string(), e.exit_code());
+        std::process::exit(e.exit_code());
+    }
+}
+
+// Print an error on stderr. Usage errors are reported by clap, and exit with 2.
+fn report(format: ErrorFormat, code: &str, message: &str, exit_code: i32) {
+    match format {
+        ErrorFormat::Text => eprintln!("Error: {message}"),
+        ErrorFormat::Json => {
+            // TOML basic strings are escaped the same way as JSON strings
+            let message = Value::String(message.to_string());
+            eprintln!(r#"{{"error":"{code}","message":{message},"exit_code":{exit_code}}}"#);
+        }
     }
 }
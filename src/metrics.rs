@@ -0,0 +1,78 @@
+//! Counters in the Prometheus text exposition format, for scraping or for
+//! node-exporter's textfile collector
+//!
+//! Each counter is labelled with its name and the file holding it:
+//!
+//! ```text
+//! counter_value{file="/tmp/count",name="count"} 5
+//! ```
+
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::Counter;
+
+/// Metrics for `counters`, stored in the file at `path`: the value (and target or
+/// countdown total, where set) as gauges, and the running totals of increments and
+/// decrements as counters
+#[must_use]
+pub fn render(counters: &[Counter], path: &Path) -> String {
+    let file = escape(&path.display().to_string());
+    let mut out = String::new();
+    let mut family =
+        |name: &str, kind: &str, help: &str, value: &dyn Fn(&Counter) -> Option<i128>| {
+            let samples: Vec<String> = counters
+                .iter()
+                .filter_map(|c| {
+                    let value = value(c)?;
+                    let label = escape(&c.name);
+                    Some(format!(
+                        "{name}{{file=\"{file}\",name=\"{label}\"}} {value}\n"
+                    ))
+                })
+                .collect();
+            if !samples.is_empty() {
+                let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} {kind}\n");
+                out.extend(samples);
+            }
+        };
+    family(
+        "counter_value",
+        "gauge",
+        "Current value of the counter",
+        &|c| Some(c.count.into()),
+    );
+    family(
+        "counter_target",
+        "gauge",
+        "Value the counter is counted toward",
+        &|c| c.target.map(i128::from),
+    );
+    family(
+        "counter_countdown_start",
+        "gauge",
+        "Value a countdown started from",
+        &|c| c.total.map(i128::from),
+    );
+    family(
+        "counter_increments_total",
+        "counter",
+        "Total amount added to the counter",
+        &|c| Some(c.increased.into()),
+    );
+    family(
+        "counter_decrements_total",
+        "counter",
+        "Total amount subtracted from the counter",
+        &|c| Some(c.decreased.into()),
+    );
+    out
+}
+
+// Escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,110 @@
+//! Live session statistics for the interactive prompt: change since the session started,
+//! rate over a sliding window, elapsed time and time to reach a target
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+pub struct RateTracker {
+    start: Instant,
+    window: Duration,
+    counters: HashMap<String, Samples>,
+}
+
+// Values seen for one counter
+struct Samples {
+    first: i64,
+    // Changes within the window, oldest first, plus the last one before it
+    recent: VecDeque<(Instant, i64)>,
+}
+
+impl RateTracker {
+    /// Tracker measuring rates over the last `window`
+    pub fn new(window: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            window,
+            counters: HashMap::new(),
+        }
+    }
+
+    /// Note the value of the counter called `name` at time `now`
+    pub fn observe(&mut self, now: Instant, name: &str, value: i64) {
+        let samples = self
+            .counters
+            .entry(name.to_string())
+            .or_insert_with(|| Samples {
+                first: value,
+                recent: VecDeque::from([(now, value)]),
+            });
+        if samples.recent.back().is_none_or(|&(_, last)| last != value) {
+            samples.recent.push_back((now, value));
+        }
+        // Keep one sample from before the window, as the value at its start
+        let window_start = now.checked_sub(self.window).unwrap_or(self.start);
+        while samples
+            .recent
+            .get(1)
+            .is_some_and(|&(t, _)| t <= window_start)
+        {
+            samples.recent.pop_front();
+        }
+    }
+
+    /// Status segment for the counter called `name`, e.g.
+    /// `+12 this session  2.4/min  5:00  ETA 3:20`
+    pub fn status(&self, now: Instant, name: &str, target: Option<i64>) -> String {
+        let Some(samples) = self.counters.get(name) else {
+            return String::new();
+        };
+        let value = samples.recent.back().map_or(samples.first, |&(_, v)| v);
+        let delta = i128::from(value) - i128::from(samples.first);
+        let mut status = format!(
+            "{delta:+} this session  {}  {}",
+            self.rate(now, samples)
+                .map_or_else(|| "-/min".to_string(), |rate| format!("{rate:.1}/min")),
+            format_duration(now.duration_since(self.start))
+        );
+
+        if let Some(target) = target {
+            let remaining = i128::from(target) - i128::from(value);
+            let eta = match self.rate(now, samples) {
+                _ if remaining == 0 => "at target".to_string(),
+                // Only moving toward the target gets there
+                Some(rate) if rate != 0.0 && (rate > 0.0) == (remaining > 0) => {
+                    #[allow(clippy::cast_precision_loss)]
+                    let seconds = remaining as f64 / rate * 60.0;
+                    Duration::try_from_secs_f64(seconds).map_or_else(
+                        |_| "ETA -".to_string(),
+                        |eta| format!("ETA {}", format_duration(eta)),
+                    )
+                }
+                _ => "ETA -".to_string(),
+            };
+            status.push_str("  ");
+            status.push_str(&eta);
+        }
+        status
+    }
+
+    // Net change per minute over the window (or the session, if shorter)
+    fn rate(&self, now: Instant, samples: &Samples) -> Option<f64> {
+        let &(since, base) = samples.recent.front()?;
+        let &(_, value) = samples.recent.back()?;
+        let window_start = now.checked_sub(self.window).unwrap_or(self.start);
+        let span = now.duration_since(since.max(window_start).max(self.start));
+        #[allow(clippy::cast_precision_loss)]
+        let change = (i128::from(value) - i128::from(base)) as f64;
+        (span >= Duration::from_secs(1)).then(|| change * 60.0 / span.as_secs_f64())
+    }
+}
+
+// "m:ss", or "h:mm:ss" from an hour up
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
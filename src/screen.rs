@@ -0,0 +1,133 @@
+//! Full-screen display: the count drawn in large segment-style digits, centered in the
+//! alternate screen, with the prompt and key help underneath
+
+use std::io::{self, Write};
+
+use crossterm::{
+    QueueableCommand, cursor,
+    style::{Print, StyledContent, Stylize},
+    terminal::{self, ClearType},
+};
+
+// Glyphs are 3 pixels wide and 5 high, one string per row
+const GLYPH_WIDTH: u16 = 3;
+const GLYPH_HEIGHT: u16 = 5;
+
+const fn glyph(c: char) -> [&'static str; 5] {
+    match c {
+        '0' => ["###", "#.#", "#.#", "#.#", "###"],
+        '1' => ["..#", "..#", "..#", "..#", "..#"],
+        '2' => ["###", "..#", "###", "#..", "###"],
+        '3' => ["###", "..#", "###", "..#", "###"],
+        '4' => ["#.#", "#.#", "###", "..#", "..#"],
+        '5' => ["###", "#..", "###", "..#", "###"],
+        '6' => ["###", "#..", "###", "#.#", "###"],
+        '7' => ["###", "..#", "..#", "..#", "..#"],
+        '8' => ["###", "#.#", "###", "#.#", "###"],
+        '9' => ["###", "#.#", "###", "..#", "###"],
+        '-' => ["...", "...", "###", "...", "..."],
+        _ => ["...", "...", "...", "...", "..."],
+    }
+}
+
+/// Draw a full frame: `title` above the big digits of `value` (in green if `highlight`),
+/// then the `prompt` and the `help` entries, wrapped to fit, below
+pub fn draw(
+    title: &str,
+    value: i64,
+    highlight: bool,
+    prompt: &str,
+    help: &[String],
+) -> io::Result<()> {
+    let (cols, rows) = terminal::size()?;
+    let mut stdout = io::stdout();
+
+    let mut lines = vec![prompt.to_string()];
+    for line in wrap(help, usize::from(cols)) {
+        lines.push(line.dim().to_string());
+    }
+    stdout.queue(terminal::Clear(ClearType::All))?;
+
+    let text = value.to_string();
+    let chars = u16::try_from(text.len()).unwrap_or(u16::MAX);
+    // Each pixel is `scale` rows high and twice as many columns wide, so that it looks
+    // roughly square; glyphs are separated by one blank pixel
+    let needed_cols = |scale: u16| (chars * (GLYPH_WIDTH + 1) - 1) * 2 * scale;
+    let free_rows = rows.saturating_sub(2 + u16::try_from(lines.len()).unwrap_or(0) * 2);
+    let scale = (1..=free_rows / GLYPH_HEIGHT)
+        .rev()
+        .find(|&scale| needed_cols(scale) <= cols);
+
+    let body_height = scale.map_or(1, |scale| GLYPH_HEIGHT * scale);
+    let top = rows.saturating_sub(body_height + 2 + u16::try_from(lines.len()).unwrap_or(0)) / 2;
+
+    let paint = |digits: StyledContent<String>| if highlight { digits.green() } else { digits };
+    centered(&mut stdout, top, cols, title)?;
+    match scale {
+        Some(scale) => {
+            let left = cols.saturating_sub(needed_cols(scale)) / 2;
+            for row in 0..GLYPH_HEIGHT * scale {
+                let mut line = String::new();
+                for (i, c) in text.chars().enumerate() {
+                    if i > 0 {
+                        line.push_str(&" ".repeat(usize::from(2 * scale)));
+                    }
+                    for pixel in glyph(c)[usize::from(row / scale)].chars() {
+                        let fill = if pixel == '#' { "█" } else { " " };
+                        line.push_str(&fill.repeat(usize::from(2 * scale)));
+                    }
+                }
+                stdout
+                    .queue(cursor::MoveTo(left, top + 1 + row))?
+                    .queue(Print(paint(line.stylize())))?;
+            }
+        }
+        // Too small for big digits
+        None => centered(&mut stdout, top + 1, cols, &paint(text.bold()).to_string())?,
+    }
+
+    for (i, line) in lines.iter().enumerate() {
+        let row = top + body_height + 2 + u16::try_from(i).unwrap_or(0);
+        centered(&mut stdout, row, cols, line)?;
+    }
+    stdout.flush()
+}
+
+// Join `entries` into as few lines of at most `width` characters as possible
+fn wrap(entries: &[String], width: usize) -> Vec<String> {
+    const SEPARATOR: &str = "   ";
+    let mut lines: Vec<String> = Vec::new();
+    for entry in entries {
+        match lines.last_mut() {
+            Some(line) if line.len() + SEPARATOR.len() + entry.len() <= width => {
+                line.push_str(SEPARATOR);
+                line.push_str(entry);
+            }
+            _ => lines.push(entry.clone()),
+        }
+    }
+    lines
+}
+
+fn centered(stdout: &mut io::Stdout, row: u16, cols: u16, text: &str) -> io::Result<()> {
+    let width = u16::try_from(visible_width(text)).unwrap_or(u16::MAX);
+    stdout
+        .queue(cursor::MoveTo(cols.saturating_sub(width) / 2, row))?
+        .queue(Print(text))?;
+    Ok(())
+}
+
+// Number of characters in `text` that take up space, skipping ANSI escape sequences
+fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip to the letter that ends a CSI sequence
+            chars.by_ref().find(char::is_ascii_alphabetic);
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
@@ -0,0 +1,138 @@
+//! Terminal setup for interactive use, undone however the program leaves it: on return,
+//! on error, on panic or on SIGTERM/SIGHUP
+
+use std::io;
+use std::panic;
+use std::sync::Once;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use counter::{Error, Result};
+use crossterm::{
+    ExecutableCommand, cursor,
+    event::{KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags},
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+
+// Changes made by the current session, so that the panic hook knows what to undo
+static STATE: AtomicU8 = AtomicU8::new(0);
+const RAW: u8 = 1; // raw mode with hidden cursor
+const ALTERNATE: u8 = 2;
+const ENHANCED: u8 = 4;
+
+/// Raw mode with a hidden cursor, and optionally the alternate screen and keyboard
+/// enhancement, until dropped
+///
+/// While a session is active, SIGTERM and SIGHUP no longer kill the process; instead
+/// `check_signal` reports them as an error, so the caller can clean up and exit.
+pub struct TerminalSession {
+    #[cfg(unix)]
+    signals: Vec<signal_hook::SigId>,
+}
+
+impl TerminalSession {
+    pub fn start(alternate: bool, enhanced: bool) -> io::Result<Self> {
+        install_panic_hook();
+        // Created first so that anything already set up is undone if a step fails
+        let session = Self {
+            #[cfg(unix)]
+            signals: signals::register()?,
+        };
+
+        terminal::enable_raw_mode()?;
+        STATE.fetch_or(RAW, Ordering::SeqCst);
+        io::stdout().execute(cursor::Hide)?;
+        if alternate {
+            STATE.fetch_or(ALTERNATE, Ordering::SeqCst);
+            io::stdout().execute(EnterAlternateScreen)?;
+        }
+        if enhanced {
+            STATE.fetch_or(ENHANCED, Ordering::SeqCst);
+            io::stdout().execute(PushKeyboardEnhancementFlags(
+                KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES,
+            ))?;
+        }
+        Ok(session)
+    }
+}
+
+impl Drop for TerminalSession {
+    fn drop(&mut self) {
+        restore();
+        #[cfg(unix)]
+        for id in self.signals.drain(..) {
+            signal_hook::low_level::unregister(id);
+        }
+    }
+}
+
+// Put the terminal back the way it was. Errors are ignored, as there is nothing better
+// to do with a terminal that can't be written to.
+fn restore() {
+    let state = STATE.swap(0, Ordering::SeqCst);
+    let mut stdout = io::stdout();
+    if state & ENHANCED != 0 {
+        let _ = stdout.execute(PopKeyboardEnhancementFlags);
+    }
+    if state & ALTERNATE != 0 {
+        let _ = stdout.execute(LeaveAlternateScreen);
+    }
+    if state & RAW != 0 {
+        let _ = stdout.execute(cursor::Show);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// Restore the terminal before the panic message is printed, so it is readable
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore();
+            default_hook(info);
+        }));
+    });
+}
+
+/// Fails with `Error::Interrupted` if a termination signal has arrived
+pub fn check_signal() -> Result<()> {
+    received_signal().map_or(Ok(()), |signal| Err(Error::Interrupted(signal)))
+}
+
+// The termination signal received during a session, if any
+fn received_signal() -> Option<i32> {
+    #[cfg(unix)]
+    return signals::received();
+    #[cfg(not(unix))]
+    None
+}
+
+#[cfg(unix)]
+mod signals {
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, LazyLock};
+
+    use signal_hook::SigId;
+    use signal_hook::consts::{SIGHUP, SIGTERM};
+
+    // Number of the last signal received, or 0
+    static RECEIVED: LazyLock<Arc<AtomicUsize>> = LazyLock::new(Arc::default);
+
+    pub fn register() -> io::Result<Vec<SigId>> {
+        [SIGTERM, SIGHUP]
+            .into_iter()
+            .map(|signal| {
+                let number = usize::try_from(signal).unwrap_or_default();
+                signal_hook::flag::register_usize(signal, Arc::clone(&RECEIVED), number)
+            })
+            .collect()
+    }
+
+    pub fn received() -> Option<i32> {
+        match RECEIVED.load(Ordering::SeqCst) {
+            0 => None,
+            number => i32::try_from(number).ok(),
+        }
+    }
+}
@@ -0,0 +1,383 @@
+//! Statistics over the changes recorded in the journal
+//!
+//! All times are UTC; weeks start on Monday.
+
+use crate::journal::{Entry, Op};
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+// The Unix epoch was a Thursday, so Mondays start 4 days later
+const WEEK_OFFSET: u64 = 4 * DAY;
+
+/// Length of the periods that changes are grouped into
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Period {
+    Hour,
+    #[default]
+    Day,
+    Week,
+}
+
+impl Period {
+    /// Start of the period containing `timestamp`
+    #[must_use]
+    pub const fn start(self, timestamp: u64) -> u64 {
+        match self {
+            Self::Hour => timestamp - timestamp % HOUR,
+            Self::Day => timestamp - timestamp % DAY,
+            Self::Week if timestamp < WEEK_OFFSET => 0,
+            Self::Week => timestamp - (timestamp - WEEK_OFFSET) % WEEK,
+        }
+    }
+
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Hour => "hour",
+            Self::Day => "day",
+            Self::Week => "week",
+        }
+    }
+}
+
+/// Totals for the changes made during a span of time
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Time of the first change, or start of the period
+    pub start: u64,
+    /// Time of the last change
+    pub end: u64,
+    /// Number of changes
+    pub changes: usize,
+    /// Sum of all increases
+    pub increase: i128,
+    /// Sum of all decreases, as a positive number
+    pub decrease: i128,
+    /// Value after the last change
+    pub value: i64,
+}
+
+impl Summary {
+    /// Net change
+    #[must_use]
+    pub const fn net(&self) -> i128 {
+        self.increase - self.decrease
+    }
+
+    // Add a change of `delta`, made at `timestamp`, that left the counter at `value`
+    const fn add(&mut self, timestamp: u64, delta: i128, value: i64) {
+        self.end = timestamp;
+        self.changes += 1;
+        if delta > 0 {
+            self.increase += delta;
+        } else {
+            self.decrease -= delta;
+        }
+        self.value = value;
+    }
+}
+
+/// Statistics for one counter
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Changes per period, oldest first (periods without changes are left out)
+    pub periods: Vec<Summary>,
+    /// Runs of changes separated by less than the session gap, oldest first
+    pub sessions: Vec<Summary>,
+    /// All changes
+    pub total: Summary,
+}
+
+impl Stats {
+    /// Summarise the changes to the counter called `name` in `entries`. Changes less than
+    /// `session_gap` seconds apart belong to the same session.
+    ///
+    /// Entries are replayed in journal order, but grouped by their timestamps, which may
+    /// go backwards (e.g. when the clock is changed).
+    #[must_use]
+    pub fn new(entries: &[Entry], name: &str, period: Period, session_gap: u64) -> Self {
+        // Each change's time, amount and resulting value
+        let mut changes = Vec::new();
+        let mut value = 0;
+        for entry in entries.iter().filter(|e| e.name == name) {
+            let old = value;
+            value = entry.op.apply(value);
+            // Snapshots restate the value (e.g. after compaction) rather than change it
+            if !matches!(entry.op, Op::Snapshot(_)) {
+                let delta = i128::from(value) - i128::from(old);
+                changes.push((entry.timestamp, delta, value));
+            }
+        }
+        changes.sort_by_key(|&(timestamp, ..)| timestamp);
+
+        let mut stats = Self::default();
+        let mut last_change: Option<u64> = None;
+        for (timestamp, delta, value) in changes {
+            let start = period.start(timestamp);
+            match stats.periods.last_mut() {
+                Some(summary) if summary.start == start => {}
+                _ => stats.periods.push(Summary {
+                    start,
+                    ..Summary::default()
+                }),
+            }
+            let new_session =
+                last_change.is_none_or(|last| timestamp > last.saturating_add(session_gap));
+            if new_session {
+                stats.sessions.push(Summary {
+                    start: timestamp,
+                    ..Summary::default()
+                });
+            }
+            if last_change.is_none() {
+                stats.total.start = timestamp;
+            }
+            last_change = Some(timestamp);
+
+            for summary in [
+                stats.periods.last_mut(),
+                stats.sessions.last_mut(),
+                Some(&mut stats.total),
+            ]
+            .into_iter()
+            .flatten()
+            {
+                summary.add(timestamp, delta, value);
+            }
+        }
+        stats
+    }
+
+    /// Up to `n` periods with the most movement (increases plus decreases), busiest
+    /// first
+    #[must_use]
+    pub fn busiest(&self, n: usize) -> Vec<&Summary> {
+        let mut periods: Vec<&Summary> = self.periods.iter().collect();
+        // Stable sort, so that ties keep the earlier period first
+        periods.sort_by_key(|p| std::cmp::Reverse(p.increase + p.decrease));
+        periods.truncate(n);
+        periods
+    }
+
+    /// Average net change per hour between the first and last change, if they are at
+    /// least a minute apart
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)] // rates don't need more than f64 precision
+    pub fn rate_per_hour(&self) -> Option<f64> {
+        let span = self.total.end.saturating_sub(self.total.start);
+        (span >= 60).then(|| self.total.net() as f64 * HOUR as f64 / span as f64)
+    }
+}
+
+/// Format a Unix timestamp as an ISO 8601 date and time in UTC, e.g.
+/// `2024-05-06T07:08:09Z`
+#[must_use]
+pub fn format_time(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days(timestamp / DAY);
+    let seconds = timestamp % DAY;
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / HOUR,
+        seconds % HOUR / 60,
+        seconds % 60
+    )
+}
+
+/// Parse a time written by [`format_time`] back into a Unix timestamp
+#[must_use]
+pub fn parse_time(text: &str) -> Option<u64> {
+    let (date, time) = text.strip_suffix('Z')?.split_once('T')?;
+    // Two-digit fields separated by `separator`, after a four-digit year for the date
+    let fields = |text: &str, separator| -> Option<Vec<u64>> {
+        let parts: Vec<&str> = text.split(separator).collect();
+        let sizes: &[usize] = if separator == '-' {
+            &[4, 2, 2]
+        } else {
+            &[2, 2, 2]
+        };
+        let valid = parts.len() == sizes.len()
+            && parts.iter().zip(sizes).all(|(part, &size)| {
+                part.len() == size && part.bytes().all(|b| b.is_ascii_digit())
+            });
+        valid.then(|| parts.iter().filter_map(|part| part.parse().ok()).collect())
+    };
+    let (date, time) = (fields(date, '-')?, fields(time, ':')?);
+    let (year, month, day) = (date[0], date[1], date[2]);
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    if time[0] > 23 || time[1] > 59 || time[2] > 59 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    // Reject dates like February 30th, which don't survive the round trip
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+    Some(days * DAY + time[0] * HOUR + time[1] * 60 + time[2])
+}
+
+// Year, month and day of the date `days` after 1970-01-01, using the algorithm from
+// http://howardhinnant.github.io/date_algorithms.html (restricted to dates after 1970)
+const fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// Number of days from 1970-01-01 to a date after it, the inverse of `civil_from_days`
+const fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let yoe = year % 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(timestamp: u64, op: Op) -> Entry {
+        Entry {
+            timestamp,
+            op,
+            name: "count".to_string(),
+        }
+    }
+
+    #[test]
+    fn period_start() {
+        // 2024-05-08 (a Wednesday) 13:14:15
+        let time = 1_715_174_055;
+        assert_eq!(Period::Hour.start(time), 1_715_173_200);
+        assert_eq!(Period::Day.start(time), 1_715_126_400);
+        // Monday 2024-05-06
+        assert_eq!(Period::Week.start(time), 1_714_953_600);
+        assert_eq!(Period::Week.start(0), 0);
+        assert_eq!(Period::Week.start(WEEK_OFFSET), WEEK_OFFSET);
+        let start = Period::Week.start(u64::MAX);
+        assert_eq!((start - WEEK_OFFSET) % WEEK, 0);
+        assert!(u64::MAX - start < WEEK);
+        assert_eq!(Period::Day.start(u64::MAX), u64::MAX - u64::MAX % DAY);
+    }
+
+    #[test]
+    fn time_round_trip() {
+        assert_eq!(format_time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_time(1_715_174_055), "2024-05-08T13:14:15Z");
+        assert_eq!(format_time(951_782_400), "2000-02-29T00:00:00Z");
+        for time in [0, 59, 951_782_400, 1_715_174_055, 253_402_300_799] {
+            assert_eq!(parse_time(&format_time(time)), Some(time));
+        }
+        // Far beyond four-digit years, formatting still works
+        assert!(format_time(u64::MAX).ends_with('Z'));
+    }
+
+    #[test]
+    fn parse_time_rejects_invalid() {
+        for text in [
+            "",
+            "2024-05-08",
+            "2024-05-08T13:14:15",
+            "2024-05-08 13:14:15Z",
+            "1969-12-31T23:59:59Z",
+            "2023-02-29T00:00:00Z",
+            "2024-13-01T00:00:00Z",
+            "2024-05-08T24:00:00Z",
+            "2024-05-08T13:60:00Z",
+            "2024-5-08T13:14:15Z",
+            "+024-05-08T13:14:15Z",
+        ] {
+            assert_eq!(parse_time(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn stats_in_order() {
+        let entries = [
+            entry(1000, Op::Snapshot(5)),
+            entry(2000, Op::Increment(3)),
+            entry(2100, Op::Decrement(1)),
+            entry(2000 + DAY, Op::Set(10)),
+        ];
+        let stats = Stats::new(&entries, "count", Period::Day, 30 * 60);
+        assert_eq!(stats.periods.len(), 2);
+        assert_eq!(stats.periods[0].changes, 2);
+        assert_eq!(stats.periods[0].net(), 2);
+        assert_eq!(stats.periods[1].value, 10);
+        assert_eq!(stats.sessions.len(), 2);
+        assert_eq!(stats.total.changes, 3);
+        assert_eq!((stats.total.increase, stats.total.decrease), (6, 1));
+        assert_eq!((stats.total.start, stats.total.end), (2000, 2000 + DAY));
+        assert!(stats.rate_per_hour().is_some_and(|rate| rate > 0.0));
+    }
+
+    #[test]
+    fn stats_out_of_order() {
+        let entries = [
+            entry(2_000_000_000, Op::Increment(1)),
+            entry(1000, Op::Increment(1)),
+            entry(2_000_000_100, Op::Increment(1)),
+            entry(1100, Op::Increment(1)),
+        ];
+        let stats = Stats::new(&entries, "count", Period::Day, 30 * 60);
+        assert_eq!(stats.periods.len(), 2);
+        assert!(stats.periods[0].start < stats.periods[1].start);
+        assert_eq!(stats.periods[0].changes, 2);
+        assert_eq!(stats.sessions.len(), 2);
+        assert_eq!((stats.total.start, stats.total.end), (1000, 2_000_000_100));
+        // Values follow the journal's order
+        assert_eq!(stats.total.value, 3);
+        assert!(stats.rate_per_hour().is_some());
+    }
+
+    #[test]
+    fn stats_extremes() {
+        let entries = [
+            entry(0, Op::Increment(i64::MAX)),
+            entry(u64::MAX, Op::Decrement(i64::MAX)),
+        ];
+        let stats = Stats::new(&entries, "count", Period::Week, u64::MAX);
+        assert_eq!(stats.sessions.len(), 1);
+        assert_eq!(stats.total.net(), 0);
+        assert!(stats.rate_per_hour().is_some());
+        assert_eq!(
+            Stats::new(&entries, "other", Period::Hour, 0),
+            Stats::default()
+        );
+    }
+
+    #[test]
+    fn rate_needs_a_minute() {
+        let stats = Stats {
+            total: Summary {
+                start: 100,
+                end: 159,
+                ..Summary::default()
+            },
+            ..Stats::default()
+        };
+        assert_eq!(stats.rate_per_hour(), None);
+        // An end before the start (from a hand-built summary) doesn't panic
+        let stats = Stats {
+            total: Summary {
+                start: 200,
+                end: 100,
+                ..Summary::default()
+            },
+            ..Stats::default()
+        };
+        assert_eq!(stats.rate_per_hour(), None);
+    }
+}
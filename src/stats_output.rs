@@ -0,0 +1,177 @@
+//! Rendering of the `stats` report as a table, CSV or JSON
+
+use std::fmt::Write;
+
+use clap::ValueEnum;
+use counter::stats::{Period, Stats, Summary, format_time};
+use counter::toml_lite::Value;
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum Format {
+    /// Aligned columns for reading
+    #[default]
+    Table,
+    /// One row per period, busiest period, session and the total, with a `kind` column
+    Csv,
+    /// A single JSON object
+    Json,
+}
+
+/// What the report is about, besides the statistics themselves
+pub struct Report<'a> {
+    pub name: &'a str,
+    pub period: Period,
+    /// Session gap in minutes
+    pub session_gap: u64,
+    /// Number of busiest periods to list
+    pub top: usize,
+}
+
+impl Report<'_> {
+    pub fn render(&self, stats: &Stats, format: Format) -> String {
+        match format {
+            Format::Table => self.table(stats),
+            Format::Csv => csv(stats, self.top),
+            Format::Json => self.json(stats),
+        }
+    }
+
+    fn table(&self, stats: &Stats) -> String {
+        let period = self.period.name();
+        if stats.total.changes == 0 {
+            return format!("No changes recorded for {}\n", self.name);
+        }
+
+        let mut out = format!("Counter {}, per {period} (UTC)\n\n", self.name);
+        let _ = writeln!(
+            out,
+            "{:<16}  {:>8}  {:>9}  {:>9}  {:>9}  {:>9}",
+            capitalize(period),
+            "Changes",
+            "Increase",
+            "Decrease",
+            "Net",
+            "Value"
+        );
+        for summary in &stats.periods {
+            let _ = writeln!(
+                out,
+                "{:<16}  {:>8}  {:>9}  {:>9}  {:>+9}  {:>9}",
+                label(self.period, summary.start),
+                summary.changes,
+                summary.increase,
+                summary.decrease,
+                summary.net(),
+                summary.value
+            );
+        }
+
+        let _ = writeln!(out, "\nBusiest {period}s");
+        for summary in stats.busiest(self.top) {
+            let _ = writeln!(
+                out,
+                "{:<16}  {:>12}, {:+}",
+                label(self.period, summary.start),
+                changes(summary.changes),
+                summary.net()
+            );
+        }
+
+        let _ = match stats.rate_per_hour() {
+            Some(rate) => writeln!(out, "\nAverage rate: {rate:+.2} per hour"),
+            None => writeln!(out, "\nAverage rate: n/a (less than a minute of changes)"),
+        };
+
+        let _ = writeln!(out, "\nSessions (gaps over {} min)", self.session_gap);
+        for session in &stats.sessions {
+            let _ = writeln!(
+                out,
+                "{} to {}  {:>12}, {:+}",
+                label(Period::Hour, session.start),
+                label(Period::Hour, session.end),
+                changes(session.changes),
+                session.net()
+            );
+        }
+        out
+    }
+
+    fn json(&self, stats: &Stats) -> String {
+        let list = |summaries: &mut dyn Iterator<Item = &Summary>| {
+            let items: Vec<String> = summaries.map(json_summary).collect();
+            format!("[{}]", items.join(","))
+        };
+        let rate = stats
+            .rate_per_hour()
+            .map_or_else(|| "null".to_string(), |rate| format!("{rate:.4}"));
+        format!(
+            r#"{{"counter":{},"period":"{}","session_gap_minutes":{},"total":{},"rate_per_hour":{rate},"periods":{},"busiest":{},"sessions":{}}}"#,
+            Value::String(self.name.to_string()),
+            self.period.name(),
+            self.session_gap,
+            json_summary(&stats.total),
+            list(&mut stats.periods.iter()),
+            list(&mut stats.busiest(self.top).into_iter()),
+            list(&mut stats.sessions.iter()),
+        ) + "\n"
+    }
+}
+
+// Date and time to the minute for hours, just the date for days and weeks
+fn label(period: Period, timestamp: u64) -> String {
+    let time = format_time(timestamp).replace('T', " ");
+    match period {
+        Period::Hour => time[..16].to_string(),
+        Period::Day | Period::Week => time[..10].to_string(),
+    }
+}
+
+fn csv(stats: &Stats, top: usize) -> String {
+    let mut out = String::from("kind,start,end,changes,increase,decrease,net,value\n");
+    let rows = (stats.periods.iter().map(|s| ("period", s)))
+        .chain(stats.busiest(top).into_iter().map(|s| ("busiest", s)))
+        .chain(stats.sessions.iter().map(|s| ("session", s)))
+        .chain((stats.total.changes > 0).then_some(("total", &stats.total)));
+    for (kind, s) in rows {
+        let _ = writeln!(
+            out,
+            "{kind},{},{},{},{},{},{},{}",
+            format_time(s.start),
+            format_time(s.end),
+            s.changes,
+            s.increase,
+            s.decrease,
+            s.net(),
+            s.value
+        );
+    }
+    out
+}
+
+fn json_summary(s: &Summary) -> String {
+    format!(
+        r#"{{"start":"{}","end":"{}","changes":{},"increase":{},"decrease":{},"net":{},"value":{}}}"#,
+        format_time(s.start),
+        format_time(s.end),
+        s.changes,
+        s.increase,
+        s.decrease,
+        s.net(),
+        s.value
+    )
+}
+
+fn changes(n: usize) -> String {
+    if n == 1 {
+        "1 change".to_string()
+    } else {
+        format!("{n} changes")
+    }
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    chars.next().map_or_else(String::new, |first| {
+        first.to_uppercase().chain(chars).collect()
+    })
+}
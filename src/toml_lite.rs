@@ -0,0 +1,550 @@
+//! Reader and writer for the small subset of TOML used by counter and config files:
+//! tables (`[name]`), `key = value` pairs, and integer, boolean, string and array values.
+
+use std::fmt::{self, Display, Write};
+use std::iter::Peekable;
+use std::str::Chars;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+    Array(Vec<Self>),
+}
+
+impl Value {
+    #[must_use]
+    pub const fn as_integer(&self) -> Option<i64> {
+        match self {
+            Self::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(n) => write!(f, "{n}"),
+            Self::Boolean(b) => write!(f, "{b}"),
+            Self::String(s) => write_quoted(f, s),
+            Self::Array(values) => {
+                f.write_char('[')?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_char(']')
+            }
+        }
+    }
+}
+
+/// A named table and its key/value pairs, in file order
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Table {
+    pub name: String,
+    pub entries: Vec<(String, Value)>,
+}
+
+impl Table {
+    #[must_use]
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            entries: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// Add a key, replacing any existing value for it
+    pub fn insert(&mut self, key: &str, value: Value) {
+        match self.entries.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.entries.push((key.to_string(), value)),
+        }
+    }
+}
+
+/// A parsed file. Keys that appear before the first `[table]` header live in a table
+/// with an empty name, which is always first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Document {
+    pub tables: Vec<Table>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self {
+            tables: vec![Table::default()],
+        }
+    }
+}
+
+impl Document {
+    /// Keys that appear before the first table header
+    #[must_use]
+    pub fn root(&self) -> &Table {
+        &self.tables[0]
+    }
+
+    #[must_use]
+    pub fn table(&self, name: &str) -> Option<&Table> {
+        self.tables[1..].iter().find(|t| t.name == name)
+    }
+
+    /// Tables with a header, in file order
+    pub fn named_tables(&self) -> impl Iterator<Item = &Table> {
+        self.tables[1..].iter()
+    }
+
+    pub fn push_table(&mut self, table: Table) {
+        self.tables.push(table);
+    }
+}
+
+impl Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for table in &self.tables {
+            if table.name.is_empty() && table.entries.is_empty() {
+                continue;
+            }
+            if !first {
+                f.write_char('\n')?;
+            }
+            first = false;
+            if !table.name.is_empty() {
+                f.write_char('[')?;
+                write_key(f, &table.name)?;
+                f.write_str("]\n")?;
+            }
+            for (key, value) in &table.entries {
+                write_key(f, key)?;
+                writeln!(f, " = {value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `key` can be written without quotes
+#[must_use]
+pub fn is_bare_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn write_key(f: &mut fmt::Formatter<'_>, key: &str) -> fmt::Result {
+    if is_bare_key(key) {
+        f.write_str(key)
+    } else {
+        write_quoted(f, key)
+    }
+}
+
+fn write_quoted(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            '\r' => f.write_str("\\r")?,
+            c if c.is_control() => write!(f, "\\u{:04X}", u32::from(c))?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse(text: &str) -> Result<Document, ParseError> {
+    Parser {
+        chars: text.chars().peekable(),
+        line: 1,
+    }
+    .document()
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            line: self.line,
+            message: message.into(),
+        })
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c == Some('\n') {
+            self.line += 1;
+        }
+        c
+    }
+
+    fn eat(&mut self, expected: char) -> bool {
+        if self.chars.peek() == Some(&expected) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    // Skip spaces and tabs, and a trailing comment if there is one
+    fn skip_inline_space(&mut self) {
+        while let Some(' ' | '\t') = self.chars.peek() {
+            self.next();
+        }
+        if self.chars.peek() == Some(&'#') {
+            while !matches!(self.chars.peek(), None | Some('\n')) {
+                self.next();
+            }
+        }
+    }
+
+    // Skip whitespace, newlines and comments
+    fn skip_space(&mut self) {
+        loop {
+            self.skip_inline_space();
+            if !self.eat('\n') && !self.eat('\r') {
+                return;
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), ParseError> {
+        self.skip_inline_space();
+        self.eat('\r');
+        match self.next() {
+            None | Some('\n') => Ok(()),
+            Some(c) => self.error(format!("unexpected '{c}' after value")),
+        }
+    }
+
+    fn document(mut self) -> Result<Document, ParseError> {
+        let mut document = Document::default();
+        loop {
+            self.skip_space();
+            match self.chars.peek() {
+                None => return Ok(document),
+                Some('[') => {
+                    self.next();
+                    self.skip_inline_space();
+                    let name = self.key()?;
+                    self.skip_inline_space();
+                    if !self.eat(']') {
+                        return self.error("expected ']' after table name");
+                    }
+                    if document.table(&name).is_some() {
+                        return self.error(format!("duplicate table [{name}]"));
+                    }
+                    self.end_of_line()?;
+                    document.push_table(Table::new(&name));
+                }
+                Some(_) => {
+                    let key = self.key()?;
+                    self.skip_inline_space();
+                    if !self.eat('=') {
+                        return self.error(format!("expected '=' after key '{key}'"));
+                    }
+                    self.skip_inline_space();
+                    let value = self.value()?;
+                    let table = document.tables.last_mut().unwrap_or_else(|| unreachable!());
+                    if table.get(&key).is_some() {
+                        return self.error(format!("duplicate key '{key}'"));
+                    }
+                    self.end_of_line()?;
+                    table.entries.push((key, value));
+                }
+            }
+        }
+    }
+
+    fn key(&mut self) -> Result<String, ParseError> {
+        match self.chars.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let mut key = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                        break;
+                    }
+                    key.push(c);
+                    self.next();
+                }
+                if key.is_empty() {
+                    return self.error("expected a key");
+                }
+                Ok(key)
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        match self.chars.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.array(),
+            Some(_) => {
+                let mut word = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '+') {
+                        break;
+                    }
+                    word.push(c);
+                    self.next();
+                }
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => word.replace('_', "").parse().map_or_else(
+                        |_| self.error(format!("invalid value '{word}'")),
+                        |n| Ok(Value::Integer(n)),
+                    ),
+                }
+            }
+            None => self.error("expected a value"),
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, ParseError> {
+        self.next(); // '['
+        let mut values = Vec::new();
+        loop {
+            self.skip_space();
+            if self.eat(']') {
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_space();
+            if !self.eat(',') {
+                self.skip_space();
+                if self.eat(']') {
+                    return Ok(Value::Array(values));
+                }
+                return self.error("expected ',' or ']' in array");
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, ParseError> {
+        self.next(); // '"'
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                None | Some('\n') => return self.error("unterminated string"),
+                Some('"') => return Ok(s),
+                Some('\\') => match self.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('n') => s.push('\n'),
+                    Some('t') => s.push('\t'),
+                    Some('r') => s.push('\r'),
+                    Some('u') => {
+                        let hex: String = (0..4).filter_map(|_| self.next()).collect();
+                        let code = hex
+                            .chars()
+                            .all(|c| c.is_ascii_hexdigit())
+                            .then(|| u32::from_str_radix(&hex, 16).ok())
+                            .flatten();
+                        match code.and_then(char::from_u32) {
+                            Some(c) => s.push(c),
+                            None => return self.error(format!("invalid escape '\\u{hex}'")),
+                        }
+                    }
+                    _ => return self.error("invalid escape sequence in string"),
+                },
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, ParseError> {
+        self.next(); // '\''
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                None | Some('\n') => return self.error("unterminated string"),
+                Some('\'') => return Ok(s),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn tables_and_values() {
+        let document = parse(
+            "top = 1\n\
+             # comment\n\
+             [first]\n\
+             n = -42  # trailing comment\n\
+             big = 1_000\n\
+             yes = true\n\
+             s = \"text\"\n\
+             literal = 'C:\\path'\n\
+             \n\
+             [\"quoted name\"]\n\
+             \"quoted key\" = false\n",
+        )
+        .unwrap();
+        assert_eq!(document.root().get("top"), Some(&Value::Integer(1)));
+        let first = document.table("first").unwrap();
+        assert_eq!(first.get("n"), Some(&Value::Integer(-42)));
+        assert_eq!(first.get("big"), Some(&Value::Integer(1000)));
+        assert_eq!(first.get("yes"), Some(&Value::Boolean(true)));
+        assert_eq!(first.get("s"), Some(&string("text")));
+        assert_eq!(first.get("literal"), Some(&string("C:\\path")));
+        let quoted = document.table("quoted name").unwrap();
+        assert_eq!(quoted.get("quoted key"), Some(&Value::Boolean(false)));
+        let names: Vec<&str> = document.named_tables().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["first", "quoted name"]);
+    }
+
+    #[test]
+    fn escapes() {
+        let document = parse(r#"s = "q\" b\\ n\n t\t r\r u\u00e9 c\u0001""#).unwrap();
+        let expected = "q\" b\\ n\n t\t r\r u\u{e9} c\u{1}";
+        assert_eq!(document.root().get("s"), Some(&string(expected)));
+
+        for invalid in [
+            r#"s = "\x""#,
+            r#"s = "\u12""#,
+            r#"s = "\u+041""#,
+            r#"s = "\uD800""#,
+            "s = \"unterminated",
+            "s = \"split\nline\"",
+        ] {
+            assert!(parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn arrays() {
+        let document = parse("a = [1, \"two\", [true]]\nb = [\n  1,\n  2,\n]\nc = []\n").unwrap();
+        let root = document.root();
+        let nested = Value::Array(vec![Value::Boolean(true)]);
+        let a = Value::Array(vec![Value::Integer(1), string("two"), nested]);
+        assert_eq!(root.get("a"), Some(&a));
+        let b = Value::Array(vec![Value::Integer(1), Value::Integer(2)]);
+        assert_eq!(root.get("b"), Some(&b));
+        assert_eq!(root.get("c"), Some(&Value::Array(Vec::new())));
+
+        assert!(parse("a = [1 2]").is_err());
+        assert!(parse("a = [1,").is_err());
+    }
+
+    #[test]
+    fn crlf() {
+        let document = parse("a = 1\r\n\r\n[t]\r\nb = \"x\" # note\r\nc = [1,\r\n2]\r\n").unwrap();
+        assert_eq!(document.root().get("a"), Some(&Value::Integer(1)));
+        let table = document.table("t").unwrap();
+        assert_eq!(table.get("b"), Some(&string("x")));
+        let c = Value::Array(vec![Value::Integer(1), Value::Integer(2)]);
+        assert_eq!(table.get("c"), Some(&c));
+    }
+
+    #[test]
+    fn duplicates() {
+        let error = parse("[t]\na = 1\na = 2\n").unwrap_err();
+        assert_eq!(error.line, 3);
+        assert!(error.message.contains("duplicate key"));
+        let error = parse("[t]\n[u]\n[t]\n").unwrap_err();
+        assert_eq!(error.line, 3);
+        assert!(error.message.contains("duplicate table"));
+        // The same key in different tables is fine
+        assert!(parse("a = 1\n[t]\na = 1\n").is_ok());
+    }
+
+    #[test]
+    fn invalid() {
+        for invalid in [
+            "a",
+            "a =",
+            "= 1",
+            "a = 1 2",
+            "a = nope",
+            "a = 99999999999999999999",
+            "[t",
+            "[t] x",
+        ] {
+            assert!(parse(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut document = Document::default();
+        document.tables[0].insert("version", Value::Integer(2));
+        let mut table = Table::new("odd name");
+        table.insert("min", Value::Integer(i64::MIN));
+        table.insert("max", Value::Integer(i64::MAX));
+        table.insert("text", string("\"quotes\" \\ \n\t\r \u{7f} é"));
+        table.insert("flag", Value::Boolean(false));
+        let array = vec![Value::Integer(1), string("a,b"), Value::Array(Vec::new())];
+        table.insert("list", Value::Array(array));
+        document.push_table(table);
+        document.push_table(Table::new("empty"));
+
+        let text = document.to_string();
+        assert_eq!(parse(&text).unwrap(), document);
+    }
+
+    #[test]
+    fn insert_replaces() {
+        let mut table = Table::new("t");
+        table.insert("a", Value::Integer(1));
+        table.insert("b", Value::Integer(2));
+        table.insert("a", Value::Integer(3));
+        let keys: Vec<&str> = table.entries.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["a", "b"]);
+        assert_eq!(table.get("a"), Some(&Value::Integer(3)));
+    }
+}
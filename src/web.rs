@@ -0,0 +1,379 @@
+//! Local HTTP server for the `serve` subcommand: a JSON API over the counters, live
+//! updates as server-sent events, and a small dashboard page
+//!
+//! - `GET /`: the dashboard
+//! - `GET /api/counters`: every counter, e.g. `[{"name":"count","value":5}]`
+//! - `GET /api/counters/NAME`: one counter
+//! - `POST /api/counters/NAME/increment?by=N`, `.../decrement?by=N` (N defaults to
+//!   `--step`, or the counter's saved step) and `.../set?value=N`: change a counter,
+//!   answering with its new state
+//! - `GET /api/events`: the list of counters as an event whenever one changes
+//! - `GET /metrics`: the counters in the Prometheus text format
+//!
+//! Errors are answered with a JSON object like the command line's `--error-format json`.
+//!
+//! Requests must be addressed to the server by IP address, `localhost` or the name it
+//! listens on, so that other sites can't reach it through DNS rebinding, and changes
+//! coming from a page on another origin are refused.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use counter::metrics;
+use counter::toml_lite::Value;
+use counter::{Counter, CounterStore, Error, FileCounter};
+
+const PAGE: &str = include_str!("dashboard.html");
+// How often to check for changes made to the file by others
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+struct Shared {
+    counter: Mutex<FileCounter>,
+    path: PathBuf,
+    // Channels of the connections following /api/events
+    subscribers: Mutex<Vec<Sender<String>>>,
+    // The --step option, which takes precedence over the counters' own
+    step: Option<i64>,
+    // Host name the server was asked to listen on, and the port it listens on
+    host: String,
+    port: u16,
+}
+
+impl Shared {
+    fn counter(&self) -> MutexGuard<'_, FileCounter> {
+        self.counter.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // The counters, including any changes made to the file by others
+    fn current(&self) -> Vec<Counter> {
+        let mut counter = self.counter();
+        _ = counter.refresh();
+        counter.counters().to_vec()
+    }
+
+    // Send the counters to every subscriber, dropping those that have gone
+    fn publish(&self, counters: &[Counter]) {
+        let event = json_counters(counters);
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+/// Serve `counter` on `address` until the process is stopped
+pub fn serve(counter: FileCounter, address: &str, step: Option<i64>) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let local = listener.local_addr()?;
+    println!("Serving the counters on http://{local}/");
+    let shared = Arc::new(Shared {
+        path: counter.path().to_path_buf(),
+        counter: Mutex::new(counter),
+        subscribers: Mutex::new(Vec::new()),
+        step,
+        host: split_host(address).0.to_ascii_lowercase(),
+        port: local.port(),
+    });
+
+    let watched = Arc::clone(&shared);
+    thread::spawn(move || follow_changes(&watched));
+    for stream in listener.incoming().flatten() {
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || handle(stream, &shared));
+    }
+    Ok(())
+}
+
+// Let subscribers know about changes made to the file by others
+fn follow_changes(shared: &Shared) {
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let mut counter = shared.counter();
+        if matches!(counter.refresh(), Ok(true)) {
+            shared.publish(counter.counters());
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    host: Option<String>,
+    origin: Option<String>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    const fn json(status: &'static str, body: String) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn error(status: &'static str, code: &str, message: &str) -> Self {
+        let body = format!(
+            r#"{{"error":"{code}","message":{}}}"#,
+            Value::String(message.to_string())
+        );
+        Self::json(status, body)
+    }
+}
+
+// Answer one request on `stream`, then close it (unless it follows events)
+fn handle(stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+    let Some(request) = read_request(&mut reader)? else {
+        return Ok(());
+    };
+    let response = match forbidden(&request, shared) {
+        Some(response) => response,
+        None if (request.method.as_str(), request.path.as_str()) == ("GET", "/api/events") => {
+            return events(stream, shared);
+        }
+        None => route(&request, shared),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
+
+// Read the request line and headers, and skip any body. None if the connection was
+// closed before a request was sent.
+fn read_request(reader: &mut impl BufRead) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut words = line.split_whitespace();
+    let method = words.next().unwrap_or_default().to_string();
+    let target = words.next().unwrap_or_default();
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let mut request = Request {
+        method,
+        path: path.to_string(),
+        query: query.to_string(),
+        host: None,
+        origin: None,
+    };
+
+    let mut length = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => length = value.parse().unwrap_or(0),
+            "host" => request.host = Some(value.to_string()),
+            "origin" => request.origin = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    io::copy(&mut reader.take(length), &mut io::sink())?;
+    Ok(Some(request))
+}
+
+// The response refusing `request` if it is addressed to another host (as with DNS
+// rebinding), or is a change sent by a page from another origin
+fn forbidden(request: &Request, shared: &Shared) -> Option<Response> {
+    if request
+        .host
+        .as_deref()
+        .is_some_and(|host| !is_own_host(host, shared))
+    {
+        return Some(Response::error(
+            "403 Forbidden",
+            "forbidden",
+            "Unknown host; use the address the server listens on",
+        ));
+    }
+    // Browsers send the origin of cross-site requests, which can't be forged by a page
+    let foreign_origin = request.origin.as_deref().is_some_and(|origin| {
+        origin
+            .strip_prefix("http://")
+            .is_none_or(|host| !is_own_host(host, shared))
+    });
+    (request.method != "GET" && foreign_origin).then(|| {
+        Response::error(
+            "403 Forbidden",
+            "forbidden",
+            "Changes from other origins are not allowed",
+        )
+    })
+}
+
+// Whether `host` (as in a Host header) names this server: an IP address, `localhost`
+// or the name it was asked to listen on, with its port
+fn is_own_host(host: &str, shared: &Shared) -> bool {
+    let (name, port) = split_host(host);
+    let name = name.to_ascii_lowercase();
+    let port_matches = port.map_or(shared.port == 80, |port| port.parse() == Ok(shared.port));
+    let name_matches = name == "localhost"
+        || name == shared.host
+        || name
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok();
+    port_matches && name_matches
+}
+
+// The name and port of `host:port`, where the name may be a bracketed IPv6 address
+fn split_host(host: &str) -> (&str, Option<&str>) {
+    let end_of_name = if host.starts_with('[') {
+        host.find(']').map_or(host.len(), |end| end + 1)
+    } else {
+        host.find(':').unwrap_or(host.len())
+    };
+    let (name, rest) = host.split_at(end_of_name);
+    (name, rest.strip_prefix(':'))
+}
+
+fn route(request: &Request, shared: &Shared) -> Response {
+    let segments: Vec<&str> = request.path.split('/').filter(|s| !s.is_empty()).collect();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", []) => Response {
+            status: "200 OK",
+            content_type: "text/html; charset=utf-8",
+            body: PAGE.to_string(),
+        },
+        ("GET", ["api", "counters"]) => Response::json("200 OK", json_counters(&shared.current())),
+        ("GET", ["api", "counters", name]) => {
+            let counters = shared.current();
+            let found = counters.iter().find(|c| c.name == *name);
+            found.map_or_else(
+                || unknown_counter(name),
+                |c| Response::json("200 OK", json_counter(c)),
+            )
+        }
+        ("GET", ["metrics"]) => {
+            let metrics = metrics::render(&shared.current(), &shared.path);
+            Response {
+                status: "200 OK",
+                content_type: "text/plain; version=0.0.4",
+                body: metrics,
+            }
+        }
+        ("POST", ["api", "counters", name, action]) => change(shared, name, action, &request.query),
+        (_, ["api", ..]) => Response::error("404 Not Found", "not-found", "No such endpoint"),
+        _ => Response::error("404 Not Found", "not-found", "Not found"),
+    }
+}
+
+// Apply `action` to the counter called `name`, with the amount from `query`
+fn change(shared: &Shared, name: &str, action: &str, query: &str) -> Response {
+    let param = |key: &str| {
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v.parse::<i64>())
+    };
+    let key = match action {
+        "increment" | "decrement" => "by",
+        "set" => "value",
+        _ => return Response::error("404 Not Found", "not-found", "No such action"),
+    };
+
+    let mut counter = shared.counter();
+    let Some(index) = counter.counters().iter().position(|c| c.name == name) else {
+        return unknown_counter(name);
+    };
+    counter.select(index);
+    let default = (key == "by").then(|| shared.step.or_else(|| counter.active().step).unwrap_or(1));
+    let ((Some(Ok(amount)), _) | (None, Some(amount))) = (param(key), default) else {
+        let message = format!("'{key}' must be a number");
+        return Response::error("400 Bad Request", "usage", &message);
+    };
+    let result = match action {
+        "increment" => counter.increment(amount),
+        "decrement" => counter.decrement(amount),
+        _ => counter.set(amount),
+    };
+    match result {
+        Ok(_) => {
+            shared.publish(counter.counters());
+            Response::json("200 OK", json_counter(counter.active()))
+        }
+        Err(e @ (Error::OutOfRange { .. } | Error::Overflow { .. })) => {
+            Response::error("409 Conflict", e.code(), &e.to_string())
+        }
+        Err(e) => Response::error("500 Internal Server Error", e.code(), &e.to_string()),
+    }
+}
+
+fn unknown_counter(name: &str) -> Response {
+    let message = format!("No counter called '{name}'");
+    Response::error("404 Not Found", "not-found", &message)
+}
+
+// Send the counters as a server-sent event now and after every change, until the client
+// goes away
+fn events(mut stream: TcpStream, shared: &Shared) -> io::Result<()> {
+    let (sender, events) = mpsc::channel();
+    let current = json_counters(shared.counter().counters());
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n\
+         data: {current}\n\n"
+    )?;
+    stream.flush()?;
+    shared
+        .subscribers
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(sender);
+    for event in events {
+        write!(stream, "data: {event}\n\n")?;
+        stream.flush()?;
+    }
+    Ok(())
+}
+
+fn json_counters(counters: &[Counter]) -> String {
+    let items: Vec<String> = counters.iter().map(json_counter).collect();
+    format!("[{}]", items.join(","))
+}
+
+fn json_counter(counter: &Counter) -> String {
+    let optional = |value: Option<i64>| value.map_or_else(|| "null".to_string(), |v| v.to_string());
+    format!(
+        r#"{{"name":{},"value":{},"target":{},"total":{},"units":{},"progress":{}}}"#,
+        Value::String(counter.name.clone()),
+        counter.count,
+        optional(counter.target),
+        optional(counter.total),
+        counter.units.as_ref().map_or_else(
+            || "null".to_string(),
+            |units| Value::String(units.clone()).to_string()
+        ),
+        counter
+            .progress()
+            .map_or_else(|| "null".to_string(), |p| format!("{p:.4}"))
+    )
+}